@@ -0,0 +1,159 @@
+//! Implementation for the ChaCha20-Poly1305 authenticated encryption construction, as described
+//! in RFC 8439.
+
+use crate::chacha::ChaCha;
+use crate::poly1305::{self, Poly1305};
+
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+fn nonce_words(nonce: &[u8; NONCE_LEN]) -> [u32; 3] {
+    let mut words = [0; 3];
+    for (w, b) in words.iter_mut().zip(nonce.chunks(4)) {
+        *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    }
+    words
+}
+
+fn pad16(poly: &mut Poly1305, len: usize) {
+    let rem = len % 16;
+    if rem > 0 {
+        poly.update(&[0; 16][rem..]);
+    }
+}
+
+fn tag(otk: &[u8; 32], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+    let mut poly = Poly1305::new(otk);
+
+    poly.update(aad);
+    pad16(&mut poly, aad.len());
+    poly.update(ciphertext);
+    pad16(&mut poly, ciphertext.len());
+    poly.update(&(aad.len() as u64).to_le_bytes());
+    poly.update(&(ciphertext.len() as u64).to_le_bytes());
+
+    poly.finish()
+}
+
+/// Returns the Poly1305 key for `nonce`, which is the start of the first ChaCha20 block, and
+/// the cipher ready to encrypt from the second block on.
+fn cipher(key: &[u8], nonce: &[u8; NONCE_LEN]) -> ([u8; 32], ChaCha) {
+    let mut cc = ChaCha::from_state(key, 0, nonce_words(nonce));
+
+    let mut otk = [0; 32];
+    otk.copy_from_slice(&cc.encrypt(&[0; 64])[..32]);
+
+    (otk, cc)
+}
+
+/// Encrypts `plaintext` and authenticates it along with the additional data `aad`, returning
+/// the ciphertext followed by the tag. A nonce must never be used twice with the same key.
+pub fn seal(key: &[u8], nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let (otk, mut cc) = cipher(key, nonce);
+
+    let mut sealed = cc.encrypt(plaintext);
+    let tag = tag(&otk, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+
+    sealed
+}
+
+/// Checks the tag at the end of `sealed` against the ciphertext before it and the additional
+/// data `aad`, and decrypts the ciphertext if they match. Returns `None` if they do not, which
+/// means that either the key is wrong or the data was modified.
+pub fn open(key: &[u8], nonce: &[u8; NONCE_LEN], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < TAG_LEN {
+        return None;
+    }
+
+    let (ciphertext, expected) = sealed.split_at(sealed.len() - TAG_LEN);
+    let (otk, mut cc) = cipher(key, nonce);
+
+    let mut exp = [0; TAG_LEN];
+    exp.copy_from_slice(expected);
+    if !poly1305::verify(&tag(&otk, aad, ciphertext), &exp) {
+        return None;
+    }
+
+    Some(cc.decrypt(ciphertext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [
+        0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+        0x88, 0x89, 0x8a, 0x8b, 0x8c, 0x8d, 0x8e, 0x8f,
+        0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9a, 0x9b, 0x9c, 0x9d, 0x9e, 0x9f,
+    ];
+    const NONCE: [u8; 12] = [
+        0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+    ];
+    const AAD: [u8; 12] = [
+        0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+    ];
+    const PLAINTEXT: &str = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+    #[test]
+    fn test_seal() {
+        let sealed = seal(&KEY, &NONCE, &AAD, PLAINTEXT.as_bytes());
+        let exp_start = [
+            0xd3, 0x1a, 0x8d, 0x34, 0x64, 0x8e, 0x60, 0xdb,
+            0x7b, 0x86, 0xaf, 0xbc, 0x53, 0xef, 0x7e, 0xc2,
+        ];
+        let exp_tag = [
+            0x1a, 0xe1, 0x0b, 0x59, 0x4f, 0x09, 0xe2, 0x6a,
+            0x7e, 0x90, 0x2e, 0xcb, 0xd0, 0x60, 0x06, 0x91,
+        ];
+
+        let exp_rest = [
+            0xa4, 0xad, 0xed, 0x51, 0x29, 0x6e, 0x08, 0xfe,
+            0xa9, 0xe2, 0xb5, 0xa7, 0x36, 0xee, 0x62, 0xd6,
+            0x3d, 0xbe, 0xa4, 0x5e, 0x8c, 0xa9, 0x67, 0x12,
+            0x82, 0xfa, 0xfb, 0x69, 0xda, 0x92, 0x72, 0x8b,
+            0x1a, 0x71, 0xde, 0x0a, 0x9e, 0x06, 0x0b, 0x29,
+            0x05, 0xd6, 0xa5, 0xb6, 0x7e, 0xcd, 0x3b, 0x36,
+            0x92, 0xdd, 0xbd, 0x7f, 0x2d, 0x77, 0x8b, 0x8c,
+            0x98, 0x03, 0xae, 0xe3, 0x28, 0x09, 0x1b, 0x58,
+            0xfa, 0xb3, 0x24, 0xe4, 0xfa, 0xd6, 0x75, 0x94,
+            0x55, 0x85, 0x80, 0x8b, 0x48, 0x31, 0xd7, 0xbc,
+            0x3f, 0xf4, 0xde, 0xf0, 0x8e, 0x4b, 0x7a, 0x9d,
+            0xe5, 0x76, 0xd2, 0x65, 0x86, 0xce, 0xc6, 0x4b,
+            0x61, 0x16,
+        ];
+
+        assert_eq!(sealed.len(), PLAINTEXT.len() + TAG_LEN);
+        assert_eq!(sealed[..16], exp_start);
+        assert_eq!(sealed[16..sealed.len() - TAG_LEN], exp_rest[..]);
+        assert_eq!(sealed[sealed.len() - TAG_LEN..], exp_tag);
+    }
+
+    // The Poly1305 key generation test vector from section 2.6.2 of RFC 8439.
+    #[test]
+    fn test_poly1305_key() {
+        let nonce = [0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7];
+        let exp = [
+            0x8a, 0xd5, 0xa0, 0x8b, 0x90, 0x5f, 0x81, 0xcc,
+            0x81, 0x50, 0x40, 0x27, 0x4a, 0xb2, 0x94, 0x71,
+            0xa8, 0x33, 0xb6, 0x37, 0xe3, 0xfd, 0x0d, 0xa5,
+            0x08, 0xdb, 0xb8, 0xe2, 0xfd, 0xd1, 0xa6, 0x46,
+        ];
+
+        assert_eq!(cipher(&KEY, &nonce).0, exp);
+    }
+
+    #[test]
+    fn test_open() {
+        let mut sealed = seal(&KEY, &NONCE, &AAD, PLAINTEXT.as_bytes());
+
+        assert_eq!(open(&KEY, &NONCE, &AAD, &sealed).unwrap(), PLAINTEXT.as_bytes());
+        assert!(open(&KEY, &NONCE, b"other", &sealed).is_none());
+        assert!(open(b"wrong key", &NONCE, &AAD, &sealed).is_none());
+
+        sealed[3] ^= 1;
+        assert!(open(&KEY, &NONCE, &AAD, &sealed).is_none());
+        assert!(open(&KEY, &NONCE, &AAD, &sealed[..10]).is_none());
+    }
+}
@@ -1,7 +1,16 @@
 //! Implementation for the ChaCha20 symmetric stream cipher.
 
+#![allow(
+    clippy::clone_on_copy,
+    clippy::needless_range_loop,
+    clippy::redundant_field_names,
+    clippy::useless_vec
+)]
+
 use std::cmp;
 
+use crate::secret::{self, Zeroize, Zeroizing};
+
 const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646E, 0x79622D32, 0x6B206574];
 
 /// A ChaCha symmetric stream cipher.
@@ -13,15 +22,15 @@ pub struct ChaCha {
 }
 
 impl ChaCha {
-    /// Creates a new ChaCha cipher from the given key.
-    pub fn new(key: &Vec<u8>) -> Self {
+    /// Creates a new ChaCha cipher from the given key. Panics if the key is empty.
+    pub fn new(key: &[u8]) -> Self {
         Self::from_state(key, 1, [0x00000000, 0x00000000, 0x00000000])
     }
 
     /// Creates a new ChaCha cipher from the given key, also setting the current state of the
-    /// counter and the values of the nonce.
-    pub fn from_state(key: &Vec<u8>, counter: u32, nonce: [u32; 3]) -> Self {
-        let key = ChaCha::expand_key(&mut key.clone());
+    /// counter and the values of the nonce. Panics if the key is empty.
+    pub fn from_state(key: &[u8], counter: u32, nonce: [u32; 3]) -> Self {
+        let key = ChaCha::expand_key(key);
 
         let mut cc = Self {
             state: [0; 16],
@@ -34,16 +43,17 @@ impl ChaCha {
         cc
     }
 
-    fn expand_key(key: &mut Vec<u8>) -> [u32; 8] {
+    /// Repeats `key` up to 32 bytes, or cuts it down to them. An empty key could never fill them.
+    fn expand_key(key: &[u8]) -> [u32; 8] {
+        assert!(!key.is_empty(), "the ChaCha key is empty");
         let mut a = [0u32; 8];
-        while key.len() < 32 {
-            key.append(&mut key.clone());
-        }
-        key.resize(32, 0);
+        // Never grown past its capacity, so no copy of the key is left behind.
+        let mut expanded = Zeroizing::new(Vec::<u8>::with_capacity(32));
+        expanded.extend(key.iter().cycle().take(32));
 
         for i in 0..a.len() {
             for j in (0..4).rev() {
-                a[i] |= (key[(i * 4) + j] as u32) << (j * 8);
+                a[i] |= (expanded[(i * 4) + j] as u32) << (j * 8);
             }
         }
         a
@@ -73,7 +83,7 @@ impl ChaCha {
     }
 
     fn block_round(&mut self) {
-        let old_state = self.state.clone();
+        let mut old_state = self.state.clone();
 
         for _ in 0..10 {
             self.quarter_round(0, 4, 8, 12);
@@ -90,10 +100,11 @@ impl ChaCha {
         for i in 0..self.state.len() {
             self.state[i] = self.state[i].wrapping_add(old_state[i]);
         }
+        secret::zero_words(&mut old_state);
     }
 
     fn serialize(&self) -> Vec<u8> {
-        let mut v = Vec::new();
+        let mut v = Vec::with_capacity(64);
 
         for i in 0..16 {
             for j in 0..4 {
@@ -105,7 +116,7 @@ impl ChaCha {
     }
 
     /// Encrypts the given plaintext, returning the ciphertext.
-    pub fn encrypt(&mut self, plaintext: &Vec<u8>) -> Vec<u8> {
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
         let mut ciphertext = Vec::<u8>::new();
         let mut ndx = 0;
 
@@ -114,11 +125,12 @@ impl ChaCha {
             self.counter += 1;
             self.block_round();
 
-            let key_stream = self.serialize();
+            let mut key_stream = self.serialize();
             let len = cmp::min(plaintext.len() - ndx, 64);
             for i in 0..len {
                 ciphertext.push(key_stream[i] ^ plaintext[ndx + i]);
             }
+            key_stream.zeroize();
             ndx += len;
         }
 
@@ -126,11 +138,19 @@ impl ChaCha {
     }
 
     /// Decrypts the given ciphertext, returning the plaintext.
-    pub fn decrypt(&mut self, plaintext: &Vec<u8>) -> Vec<u8> {
+    pub fn decrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
         self.encrypt(plaintext)
     }
 }
 
+impl Drop for ChaCha {
+    /// Zeroes the key and the state derived from it.
+    fn drop(&mut self) {
+        secret::zero_words(&mut self.state);
+        secret::zero_words(&mut self.key);
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
@@ -297,4 +317,17 @@ pub mod test {
             assert_eq!(cc.decrypt(&cipher[i]), Vec::from(plain[i]));
         }
     }
+
+    #[test]
+    fn test_expand_key() {
+        let repeated = ChaCha::expand_key(&b"abc".repeat(11));
+        assert_eq!(ChaCha::expand_key(b"abc"), repeated);
+        assert_eq!(ChaCha::expand_key(&b"abc".repeat(20)), repeated);
+    }
+
+    #[test]
+    #[should_panic(expected = "the ChaCha key is empty")]
+    fn test_empty_key() {
+        ChaCha::new(b"");
+    }
 }
@@ -0,0 +1,59 @@
+//! Process hardening, to keep secrets from ending up on disk: memory holding keys and passwords
+//! can be locked so it is never swapped out, and core dumps can be disabled so a crash does not
+//! write the memory of the process to a file.
+
+use std::io;
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Prevents the process from writing core dumps, and from being attached to with `ptrace` by
+/// other processes of the same user.
+pub fn disable_core_dumps() -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    check(unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) })?;
+    check(unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) })
+}
+
+/// Locks the memory pages holding the `len` bytes at `ptr` into RAM, so they are never swapped
+/// out.
+///
+/// The amount of memory a process can lock is limited by `RLIMIT_MEMLOCK`, so this can fail for
+/// large buffers. Locks do not stack, so unlocking a buffer also unlocks any other buffer sharing
+/// one of its pages.
+pub fn lock(ptr: *const u8, len: usize) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    check(unsafe { libc::mlock(ptr as *const libc::c_void, len) })
+}
+
+/// Unlocks memory locked by `lock`.
+pub fn unlock(ptr: *const u8, len: usize) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    check(unsafe { libc::munlock(ptr as *const libc::c_void, len) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let buf = [0u8; 100];
+
+        lock(buf.as_ptr(), buf.len()).unwrap();
+        unlock(buf.as_ptr(), buf.len()).unwrap();
+        lock(buf.as_ptr(), 0).unwrap();
+    }
+}
@@ -1,12 +1,25 @@
 //! This library for the Crypt program contains my implementations for the ChaCha20 stream
-//! cipher, as well as for the Mersenne Twister psuedo-random number generator.
+//! cipher and the Poly1305 authenticator (combined into ChaCha20-Poly1305), as well as for the
+//! Mersenne Twister psuedo-random number generator.
 //!
 //! They both pass the test vectors that were provided in their respective papers.
 //!
 //! It also includes some custom trait implementstions for `Stdin` to make reading console
-//! input slightly simpler.
+//! input slightly simpler, a password strength estimator, a wrapper which zeroes secrets once
+//! they are dropped, some hardening to keep secrets out of swap and core dumps, and padding to
+//! hide the length of data, as well as the SHA-256 hash function to tell whether files changed,
+//! and PBKDF2 to derive keys from passwords.
 
+pub mod aead;
 pub mod chacha;
+pub mod hardening;
 pub mod mersenne_twister;
+pub mod padding;
+pub mod pbkdf2;
+pub mod poly1305;
+pub mod random;
+pub mod secret;
+pub mod sha256;
+pub mod strength;
 
 pub mod stdin_extras;
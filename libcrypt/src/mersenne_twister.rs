@@ -1,5 +1,11 @@
 //! Implementation for the Mersenne-Twister pseudo-random number generator.
 
+#![allow(
+    clippy::identity_op,
+    clippy::needless_range_loop,
+    clippy::should_implement_trait
+)]
+
 const STATE_SIZE: usize = 312;
 const HALF_SIZE: usize = STATE_SIZE / 2;
 
@@ -0,0 +1,101 @@
+//! Padding, to hide the exact length of data before it is encrypted.
+//!
+//! The data is followed by a `0x80` byte and as many zeroes as the padding scheme asks for, so the
+//! padding can be removed without storing the original length (ISO/IEC 7816-4).
+
+/// How much data is padded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Padding {
+    /// PADMÉ, which leaks at most O(log log n) bits of the length n, and adds at most 12% to it.
+    /// See "Reducing Metadata Leakage from Encrypted Files and Communication with PURBs".
+    Padme,
+    /// Rounds the length up to a multiple of the given block size.
+    Block(usize),
+}
+
+impl Padding {
+    /// Returns the length `len` bytes are padded to.
+    pub fn padded_len(&self, len: usize) -> usize {
+        match *self {
+            Padding::Padme => padme(len),
+            Padding::Block(size) => match len % size {
+                0 => len,
+                rem => len + size - rem,
+            },
+        }
+    }
+}
+
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+
+    // The length has `e + 1` bits, of which only the top `log2(e) + 1` are kept.
+    let e = usize::BITS - 1 - len.leading_zeros();
+    let s = u32::BITS - e.leading_zeros();
+    let mask = (1 << (e - s)) - 1;
+
+    (len + mask) & !mask
+}
+
+/// Pads `data` according to `padding`. There is always at least one byte of padding.
+pub fn pad(data: &mut Vec<u8>, padding: Padding) {
+    let len = padding.padded_len(data.len() + 1);
+
+    data.reserve_exact(len - data.len());
+    data.push(0x80);
+    data.resize(len, 0);
+}
+
+/// Removes the padding added by `pad`, returning `None` if there is none.
+pub fn unpad(data: &[u8]) -> Option<&[u8]> {
+    match data.iter().rposition(|&b| b != 0) {
+        Some(i) if data[i] == 0x80 => Some(&data[..i]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padme() {
+        let exp = [
+            (0, 0),
+            (1, 1),
+            (2, 2),
+            (9, 10),
+            (100, 104),
+            (1000, 1024),
+            (1025, 1088),
+            (1_000_000, 1_015_808),
+        ];
+
+        for (len, padded) in exp.iter() {
+            assert_eq!(Padding::Padme.padded_len(*len), *padded);
+        }
+    }
+
+    #[test]
+    fn test_block() {
+        assert_eq!(Padding::Block(512).padded_len(1), 512);
+        assert_eq!(Padding::Block(512).padded_len(512), 512);
+        assert_eq!(Padding::Block(512).padded_len(513), 1024);
+    }
+
+    #[test]
+    fn test_pad() {
+        for data in [&b""[..], b"\x80", b"hello\0\0", b"\0"].iter() {
+            let mut padded = data.to_vec();
+            pad(&mut padded, Padding::Block(16));
+
+            assert_eq!(padded.len(), 16);
+            assert_eq!(unpad(&padded), Some(*data));
+        }
+
+        assert_eq!(unpad(b"no padding"), None);
+        assert_eq!(unpad(b"\0\0"), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Implementation for HMAC-SHA256, as specified in RFC 2104, and for PBKDF2 on top of it, as
+//! specified in RFC 8018, which derives keys from passwords.
+
+use crate::sha256::Sha256;
+
+/// An HMAC-SHA256 authenticator of a message fed to it in any number of pieces.
+#[derive(Clone)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    /// Creates a new authenticator from `key`, which is hashed first if it is longer than a block.
+    pub fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        match key.len() > 64 {
+            true => block[..32].copy_from_slice(&Sha256::digest(key)),
+            false => block[..key.len()].copy_from_slice(key),
+        }
+
+        let mut inner = Sha256::new();
+        let mut outer = Sha256::new();
+        inner.update(&block.map(|b| b ^ 0x36));
+        outer.update(&block.map(|b| b ^ 0x5c));
+
+        Self { inner, outer }
+    }
+
+    /// Adds `data` to the authenticated message.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Finishes the message, returning its 32 byte tag.
+    pub fn finish(self) -> [u8; 32] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+
+    /// Computes the tag of `message` under `key`.
+    pub fn mac(key: &[u8], message: &[u8]) -> [u8; 32] {
+        let mut hmac = Self::new(key);
+        hmac.update(message);
+        hmac.finish()
+    }
+}
+
+/// Fills `out` with a key derived from `password` and `salt` with PBKDF2-HMAC-SHA256, which
+/// takes `iterations` HMACs for every 32 bytes of it.
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    // The password only has to be hashed into the HMAC once.
+    let hmac = HmacSha256::new(password);
+
+    for (i, block) in (1u32..).zip(out.chunks_mut(32)) {
+        let mut mac = hmac.clone();
+        mac.update(salt);
+        mac.update(&i.to_be_bytes());
+        let mut u = mac.finish();
+        let mut t = u;
+
+        for _ in 1..iterations {
+            let mut mac = hmac.clone();
+            mac.update(&u);
+            u = mac.finish();
+
+            for (t, u) in t.iter_mut().zip(&u) {
+                *t ^= u;
+            }
+        }
+
+        block.copy_from_slice(&t[..block.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_hmac() {
+        assert_eq!(
+            hex(&HmacSha256::mac(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(&HmacSha256::mac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&HmacSha256::mac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        let mut out = [0; 32];
+        pbkdf2_sha256(b"password", b"salt", 1, &mut out);
+        assert_eq!(
+            hex(&out),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+
+        pbkdf2_sha256(b"password", b"salt", 2, &mut out);
+        assert_eq!(
+            hex(&out),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+
+        pbkdf2_sha256(b"password", b"salt", 4096, &mut out);
+        assert_eq!(
+            hex(&out),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2_blocks() {
+        let mut out = [0; 64];
+        pbkdf2_sha256(b"passwd", b"salt", 1, &mut out);
+        assert_eq!(
+            hex(&out),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+}
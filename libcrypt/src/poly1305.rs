@@ -0,0 +1,285 @@
+//! Implementation for the Poly1305 one-time message authenticator.
+//!
+//! The 130-bit arithmetic is done with five 26-bit limbs, following Andrew Moon's
+//! poly1305-donna.
+
+const MASK: u32 = 0x3ffffff;
+
+/// A Poly1305 message authenticator. A key must never be used for more than one message.
+pub struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+    buffer: [u8; 16],
+    buffered: usize,
+}
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+impl Poly1305 {
+    /// Creates a new authenticator from the given 32 byte one-time key.
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            r: [
+                le32(&key[0..]) & 0x3ffffff,
+                (le32(&key[3..]) >> 2) & 0x3ffff03,
+                (le32(&key[6..]) >> 4) & 0x3ffc0ff,
+                (le32(&key[9..]) >> 6) & 0x3f03fff,
+                (le32(&key[12..]) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [le32(&key[16..]), le32(&key[20..]), le32(&key[24..]), le32(&key[28..])],
+            buffer: [0; 16],
+            buffered: 0,
+        }
+    }
+
+    fn block(&mut self, m: &[u8; 16], hibit: u32) {
+        let [r0, r1, r2, r3, r4] = self.r;
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+        let h0 = self.h[0] + (le32(&m[0..]) & MASK);
+        let h1 = self.h[1] + ((le32(&m[3..]) >> 2) & MASK);
+        let h2 = self.h[2] + ((le32(&m[6..]) >> 4) & MASK);
+        let h3 = self.h[3] + ((le32(&m[9..]) >> 6) & MASK);
+        let h4 = self.h[4] + ((le32(&m[12..]) >> 8) | hibit);
+
+        let mul = |a: u32, b: u32| a as u64 * b as u64;
+
+        let d0 = mul(h0, r0) + mul(h1, s4) + mul(h2, s3) + mul(h3, s2) + mul(h4, s1);
+        let mut d1 = mul(h0, r1) + mul(h1, r0) + mul(h2, s4) + mul(h3, s3) + mul(h4, s2);
+        let mut d2 = mul(h0, r2) + mul(h1, r1) + mul(h2, r0) + mul(h3, s4) + mul(h4, s3);
+        let mut d3 = mul(h0, r3) + mul(h1, r2) + mul(h2, r1) + mul(h3, r0) + mul(h4, s4);
+        let mut d4 = mul(h0, r4) + mul(h1, r3) + mul(h2, r2) + mul(h3, r1) + mul(h4, r0);
+
+        let mut c = d0 >> 26;
+        let mut h0 = d0 as u32 & MASK;
+        d1 += c;
+        c = d1 >> 26;
+        let mut h1 = d1 as u32 & MASK;
+        d2 += c;
+        c = d2 >> 26;
+        let h2 = d2 as u32 & MASK;
+        d3 += c;
+        c = d3 >> 26;
+        let h3 = d3 as u32 & MASK;
+        d4 += c;
+        c = d4 >> 26;
+        let h4 = d4 as u32 & MASK;
+        h0 += c as u32 * 5;
+        let c = h0 >> 26;
+        h0 &= MASK;
+        h1 += c;
+
+        self.h = [h0, h1, h2, h3, h4];
+    }
+
+    /// Adds `data` to the authenticated message.
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.buffered > 0 {
+            let n = (16 - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+
+            if self.buffered < 16 {
+                return;
+            }
+            let block = self.buffer;
+            self.block(&block, 1 << 24);
+            self.buffered = 0;
+        }
+
+        while data.len() >= 16 {
+            let mut block = [0; 16];
+            block.copy_from_slice(&data[..16]);
+            self.block(&block, 1 << 24);
+            data = &data[16..];
+        }
+
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    /// Finishes the message, returning its 16 byte tag.
+    pub fn finish(mut self) -> [u8; 16] {
+        if self.buffered > 0 {
+            let mut block = [0; 16];
+            block[..self.buffered].copy_from_slice(&self.buffer[..self.buffered]);
+            block[self.buffered] = 1;
+            self.block(&block, 0);
+        }
+
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+
+        // Fully carry h.
+        let mut c = h1 >> 26;
+        h1 &= MASK;
+        h2 += c;
+        c = h2 >> 26;
+        h2 &= MASK;
+        h3 += c;
+        c = h3 >> 26;
+        h3 &= MASK;
+        h4 += c;
+        c = h4 >> 26;
+        h4 &= MASK;
+        h0 += c * 5;
+        c = h0 >> 26;
+        h0 &= MASK;
+        h1 += c;
+
+        // Compute h + -p, and select it if h >= p.
+        let mut g0 = h0.wrapping_add(5);
+        c = g0 >> 26;
+        g0 &= MASK;
+        let mut g1 = h1.wrapping_add(c);
+        c = g1 >> 26;
+        g1 &= MASK;
+        let mut g2 = h2.wrapping_add(c);
+        c = g2 >> 26;
+        g2 &= MASK;
+        let mut g3 = h3.wrapping_add(c);
+        c = g3 >> 26;
+        g3 &= MASK;
+        let g4 = h4.wrapping_add(c).wrapping_sub(1 << 26);
+
+        let mask = (g4 >> 31).wrapping_sub(1);
+        h0 = (h0 & !mask) | (g0 & mask);
+        h1 = (h1 & !mask) | (g1 & mask);
+        h2 = (h2 & !mask) | (g2 & mask);
+        h3 = (h3 & !mask) | (g3 & mask);
+        h4 = (h4 & !mask) | (g4 & mask);
+
+        // h = h % 2^128 + pad
+        let words = [
+            h0 | (h1 << 26),
+            (h1 >> 6) | (h2 << 20),
+            (h2 >> 12) | (h3 << 14),
+            (h3 >> 18) | (h4 << 8),
+        ];
+
+        let mut tag = [0; 16];
+        let mut f = 0u64;
+        for i in 0..4 {
+            f = words[i] as u64 + self.pad[i] as u64 + (f >> 32);
+            tag[i * 4..i * 4 + 4].copy_from_slice(&(f as u32).to_le_bytes());
+        }
+        tag
+    }
+
+    /// Computes the tag of `message` with the one-time `key`.
+    pub fn mac(key: &[u8; 32], message: &[u8]) -> [u8; 16] {
+        let mut poly = Self::new(key);
+        poly.update(message);
+        poly.finish()
+    }
+}
+
+/// Compares two tags in constant time.
+pub fn verify(a: &[u8; 16], b: &[u8; 16]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac() {
+        let key = [
+            0x85, 0xd6, 0xbe, 0x78, 0x57, 0x55, 0x6d, 0x33,
+            0x7f, 0x44, 0x52, 0xfe, 0x42, 0xd5, 0x06, 0xa8,
+            0x01, 0x03, 0x80, 0x8a, 0xfb, 0x0d, 0xb2, 0xfd,
+            0x4a, 0xbf, 0xf6, 0xaf, 0x41, 0x49, 0xf5, 0x1b,
+        ];
+        let exp = [
+            0xa8, 0x06, 0x1d, 0xc1, 0x30, 0x51, 0x36, 0xc6,
+            0xc2, 0x2b, 0x8b, 0xaf, 0x0c, 0x01, 0x27, 0xa9,
+        ];
+
+        assert_eq!(Poly1305::mac(&key, b"Cryptographic Forum Research Group"), exp);
+    }
+
+    fn key(r: &[u8], s: &[u8]) -> [u8; 32] {
+        let mut key = [0; 32];
+        key[..r.len()].copy_from_slice(r);
+        key[16..16 + s.len()].copy_from_slice(s);
+        key
+    }
+
+    // The test vectors from appendix A.3 of RFC 8439, which cover the carries and the final
+    // reduction modulo 2^130 - 5.
+    #[test]
+    fn test_vectors() {
+        assert_eq!(Poly1305::mac(&[0; 32], &[0; 64]), [0; 16]);
+
+        // With r = 0 the tag is s, whatever the message.
+        let s = [
+            0x36, 0xe5, 0xf6, 0xb5, 0xc5, 0xe0, 0x60, 0x70,
+            0xf0, 0xef, 0xca, 0x96, 0x22, 0x7a, 0x86, 0x3e,
+        ];
+        assert_eq!(Poly1305::mac(&key(&[], &s), b"Any submission to the IETF"), s);
+
+        let mut exp = [0; 16];
+        exp[0] = 3;
+        let mut two = [0; 16];
+        two[0] = 2;
+        assert_eq!(Poly1305::mac(&key(&[2], &[]), &[0xff; 16]), exp);
+        assert_eq!(Poly1305::mac(&key(&[2], &[0xff; 16]), &two), exp);
+
+        let message = [&[0xff; 16][..], &[0xf0], &[0xff; 15], &[0x11], &[0; 15]].concat();
+        exp[0] = 5;
+        assert_eq!(Poly1305::mac(&key(&[1], &[]), &message), exp);
+
+        let message = [&[0xff; 16][..], &[0xfb], &[0xfe; 15], &[0x01; 16]].concat();
+        assert_eq!(Poly1305::mac(&key(&[1], &[]), &message), [0; 16]);
+
+        let message = [&[0xfd][..], &[0xff; 15]].concat();
+        let exp = [&[0xfa][..], &[0xff; 15]].concat();
+        assert_eq!(Poly1305::mac(&key(&[2], &[]), &message)[..], exp[..]);
+
+        let r = [1, 0, 0, 0, 0, 0, 0, 0, 4];
+        let message = [
+            0xe3, 0x35, 0x94, 0xd7, 0x50, 0x5e, 0x43, 0xb9,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x33, 0x94, 0xd7, 0x50, 0x5e, 0x43, 0x79, 0xcd,
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut exp = [0; 16];
+        exp[0] = 0x14;
+        exp[8] = 0x55;
+        assert_eq!(Poly1305::mac(&key(&r, &[]), &message), exp);
+
+        let mut exp = [0; 16];
+        exp[0] = 0x13;
+        assert_eq!(Poly1305::mac(&key(&r, &[]), &message[..48]), exp);
+    }
+
+    #[test]
+    fn test_update() {
+        let key = [7; 32];
+        let message = (0..200).map(|i| i as u8).collect::<Vec<u8>>();
+        let exp = Poly1305::mac(&key, &message);
+
+        for split in &[1, 15, 16, 17, 100] {
+            let mut poly = Poly1305::new(&key);
+            for chunk in message.chunks(*split) {
+                poly.update(chunk);
+            }
+            assert_eq!(poly.finish(), exp);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(verify(&[1; 16], &[1; 16]));
+        assert!(!verify(&[1; 16], &[2; 16]));
+    }
+}
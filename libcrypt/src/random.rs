@@ -0,0 +1,54 @@
+//! Cryptographically secure random bytes, straight from the operating system.
+//!
+//! Unlike the Mersenne Twister, these are suitable for nonces and keys.
+
+use std::io;
+
+/// Fills `buf` with random bytes.
+pub fn fill(buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        let res = unsafe {
+            libc::getrandom(
+                buf[filled..].as_mut_ptr() as *mut libc::c_void,
+                buf.len() - filled,
+                0,
+            )
+        };
+
+        if res < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        } else {
+            filled += res as usize;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns a Vec filled with `n` random bytes.
+pub fn bytes(n: usize) -> io::Result<Vec<u8>> {
+    let mut v = vec![0; n];
+    fill(&mut v)?;
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let mut a = [0u8; 64];
+        let mut b = [0u8; 64];
+        fill(&mut a).unwrap();
+        fill(&mut b).unwrap();
+
+        assert_ne!(a, b);
+        assert_eq!(bytes(1000).unwrap().len(), 1000);
+    }
+}
@@ -0,0 +1,192 @@
+//! A wrapper for buffers holding secrets, such as keys and passwords, which overwrites their
+//! contents with zeroes once they are no longer needed.
+
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+use crate::hardening;
+
+/// Types whose memory can be securely overwritten with zeroes.
+pub trait Zeroize {
+    /// Overwrites the whole allocation with zeroes, in a way the compiler will not optimise away.
+    fn zeroize(&mut self);
+
+    /// Returns the start and length of the allocation.
+    fn allocation(&self) -> (*const u8, usize);
+}
+
+fn zero_bytes(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        unsafe {
+            ptr::write_volatile(ptr.add(i), 0);
+        }
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+impl Zeroize for Vec<u8> {
+    fn zeroize(&mut self) {
+        self.clear();
+        zero_bytes(self.as_mut_ptr(), self.capacity());
+    }
+
+    fn allocation(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.capacity())
+    }
+}
+
+impl Zeroize for String {
+    fn zeroize(&mut self) {
+        unsafe { self.as_mut_vec() }.zeroize();
+    }
+
+    fn allocation(&self) -> (*const u8, usize) {
+        (self.as_ptr(), self.capacity())
+    }
+}
+
+impl Zeroize for Vec<char> {
+    fn zeroize(&mut self) {
+        self.clear();
+        zero_bytes(self.as_mut_ptr() as *mut u8, self.capacity() * 4);
+    }
+
+    fn allocation(&self) -> (*const u8, usize) {
+        (self.as_ptr() as *const u8, self.capacity() * 4)
+    }
+}
+
+/// Overwrites `words` with zeroes, for secrets kept in arrays rather than in a `Zeroizing`.
+pub fn zero_words(words: &mut [u32]) {
+    zero_bytes(words.as_mut_ptr() as *mut u8, words.len() * 4);
+}
+
+fn lock<T: Zeroize>(value: &T) {
+    let (ptr, len) = value.allocation();
+    // Locking is best effort, as the amount of memory that can be locked is limited.
+    let _ = hardening::lock(ptr, len);
+}
+
+fn unlock<T: Zeroize>(value: &T) {
+    let (ptr, len) = value.allocation();
+    let _ = hardening::unlock(ptr, len);
+}
+
+/// Holds a value which is zeroed when dropped. Its memory is also locked, so that it is not
+/// swapped out to disk.
+///
+/// Note that growing a `Vec` or `String` past its capacity moves it to a new allocation, leaving
+/// a copy of the secret behind, so reserve enough space up front or use `push_secure`.
+pub struct Zeroizing<T: Zeroize>(T);
+
+impl<T: Zeroize> Zeroizing<T> {
+    pub fn new(value: T) -> Self {
+        lock(&value);
+        Self(value)
+    }
+}
+
+impl<T: Zeroize + Default> Default for Zeroizing<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl Zeroizing<Vec<u8>> {
+    /// Appends `bytes`, zeroing the old allocation if the buffer has to grow.
+    pub fn push_secure(&mut self, bytes: &[u8]) {
+        if self.0.len() + bytes.len() > self.0.capacity() {
+            let mut grown = Vec::with_capacity((self.0.len() + bytes.len()).max(64) * 2);
+            lock(&grown);
+            grown.extend_from_slice(&self.0);
+            self.0.zeroize();
+            unlock(&self.0);
+            self.0 = grown;
+        }
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+impl Zeroizing<String> {
+    /// Appends `c`, zeroing the old allocation if the buffer has to grow.
+    pub fn push_secure(&mut self, c: char) {
+        if self.0.len() + c.len_utf8() > self.0.capacity() {
+            let mut grown = String::with_capacity((self.0.len() + c.len_utf8()).max(64) * 2);
+            lock(&grown);
+            grown.push_str(&self.0);
+            self.0.zeroize();
+            unlock(&self.0);
+            self.0 = grown;
+        }
+        self.0.push(c);
+    }
+
+    /// Converts into the bytes of the string, without copying them.
+    pub fn into_bytes(mut self) -> Zeroizing<Vec<u8>> {
+        // The allocation stays the same, and so does its lock.
+        Zeroizing(std::mem::take(&mut self.0).into_bytes())
+    }
+}
+
+impl<T: Zeroize> From<T> for Zeroizing<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Zeroize> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zeroize() {
+        let mut v = vec![1u8, 2, 3];
+        v.zeroize();
+        assert!(v.is_empty());
+        unsafe {
+            v.set_len(3);
+        }
+        assert_eq!(v, [0, 0, 0]);
+
+        let mut c = vec!['a', 'b'];
+        c.zeroize();
+        unsafe {
+            c.set_len(2);
+        }
+        assert_eq!(c, ['\0', '\0']);
+    }
+
+    #[test]
+    fn test_push_secure() {
+        let mut s = Zeroizing::new(String::new());
+        for c in "hunter2 and more characters than fit in the first allocation of the string".chars() {
+            s.push_secure(c);
+        }
+        assert_eq!(s.as_str(), "hunter2 and more characters than fit in the first allocation of the string");
+
+        let b = s.into_bytes();
+        assert_eq!(&b[..7], b"hunter2");
+    }
+}
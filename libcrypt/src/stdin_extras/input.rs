@@ -1,35 +1,291 @@
+use crate::secret::Zeroizing;
+use crate::stdin_extras::masked::read_masked_line;
 use crate::stdin_extras::read_hidden::ReadHidden;
-use std::io::{self, Stdin, Write};
+use crate::stdin_extras::terminal::Terminal;
+use std::fmt::Display;
+use std::io::{self, BufRead, Stdin, Write};
+use std::str::FromStr;
 
-/// Provides methods to simultaneously print a prompt message to `Stdout` as well as read the
-/// response from `Stdin`.
+/// Provides methods to simultaneously print a prompt message as well as read the response.
+///
+/// The typed prompts keep asking until they get a valid answer, so they only fail if reading
+/// fails, or if the input ends before a valid answer was given.
 pub trait Input {
-    fn input(&self, prompt: &str) -> io::Result<String>;
-    fn input_hidden(&self, prompt: &str) -> io::Result<String>;
+    fn input(&mut self, prompt: &str) -> io::Result<String>;
+    fn input_hidden(&mut self, prompt: &str) -> io::Result<String>;
+    fn input_masked(&mut self, prompt: &str, mask: char) -> io::Result<Zeroizing<String>>;
+
+    /// Prints `msg` on a line of its own.
+    fn message(&mut self, msg: &str) -> io::Result<()>;
+
+    /// Prints `prompt` until the user's input passes `validate`, then returns the input. If the
+    /// input is invalid, `validate` returns the message which is shown before asking again.
+    fn input_validated<F>(&mut self, prompt: &str, mut validate: F) -> io::Result<String>
+    where
+        F: FnMut(&str) -> Result<(), String>,
+    {
+        loop {
+            let val = self.input(prompt)?;
+            match validate(&val) {
+                Ok(()) => return Ok(val),
+                Err(msg) => self.message(&msg)?,
+            }
+        }
+    }
+
+    /// Prints `prompt` until the user's input can be parsed as a `T`, then returns it.
+    fn input_parse<T>(&mut self, prompt: &str) -> io::Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        loop {
+            match self.input(prompt)?.parse() {
+                Ok(val) => return Ok(val),
+                Err(e) => self.message(&format!("Invalid input: {}", e))?,
+            }
+        }
+    }
+
+    /// Asks a yes or no question. If `default` is given, it is used when the user just presses
+    /// enter.
+    fn confirm(&mut self, prompt: &str, default: Option<bool>) -> io::Result<bool> {
+        let hint = match default {
+            Some(true) => "[Y/n]",
+            Some(false) => "[y/N]",
+            None => "[y/n]",
+        };
+
+        loop {
+            match self.input(&format!("{} {}", prompt, hint))?.to_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                "" if default.is_some() => return Ok(default.unwrap()),
+                _ => self.message("Please answer 'yes' or 'no'.")?,
+            }
+        }
+    }
+
+    /// Lets the user pick one of `choices`, either by its number or by its name, and returns
+    /// its index. If `default` is given, it is used when the user just presses enter.
+    fn choose(
+        &mut self,
+        prompt: &str,
+        choices: &[&str],
+        default: Option<usize>,
+    ) -> io::Result<usize> {
+        for (i, choice) in choices.iter().enumerate() {
+            self.message(&format!("  {}) {}", i + 1, choice))?;
+        }
+        let prompt = match default {
+            Some(i) => format!("{} [1-{}, default {}]", prompt, choices.len(), i + 1),
+            None => format!("{} [1-{}]", prompt, choices.len()),
+        };
+
+        loop {
+            let val = self.input(&prompt)?;
+
+            if val.is_empty() {
+                if let Some(i) = default {
+                    return Ok(i);
+                }
+            }
+            if let Ok(n) = val.parse::<usize>() {
+                if n >= 1 && n <= choices.len() {
+                    return Ok(n - 1);
+                }
+            }
+            if let Some(i) = choices.iter().position(|c| c.eq_ignore_ascii_case(&val)) {
+                return Ok(i);
+            }
+
+            self.message(&format!(
+                "Please pick a number from 1 to {}.",
+                choices.len()
+            ))?;
+        }
+    }
 }
 
-impl Input for Stdin {
+impl<R: BufRead, W: Write> Input for Terminal<R, W> {
     /// Prints `prompt`, then returns the user's input.
-    fn input(&self, prompt: &str) -> io::Result<String> {
-        print!("{} ", prompt);
-        io::stdout().flush().unwrap();
+    fn input(&mut self, prompt: &str) -> io::Result<String> {
+        write!(self, "{} ", prompt)?;
+        self.flush()?;
 
         let mut val = String::new();
-        match io::stdin().read_line(&mut val) {
+        match self.read_line(&mut val) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no input")),
             Ok(_) => Ok(String::from(val.trim())),
             Err(e) => Err(e),
         }
     }
 
-    /// Prints `prompt`, then returns the user's input without echoing to `Stdout`.
-    fn input_hidden(&self, prompt: &str) -> io::Result<String> {
-        print!("{} ", prompt);
-        io::stdout().flush().unwrap();
+    /// Prints `prompt`, then returns the user's input without echoing it.
+    fn input_hidden(&mut self, prompt: &str) -> io::Result<String> {
+        write!(self, "{} ", prompt)?;
+        self.flush()?;
 
         let mut val = String::new();
-        match io::stdin().read_hidden_line(&mut val) {
+        match self.read_hidden_line(&mut val) {
             Ok(_) => Ok(String::from(val.trim())),
             Err(e) => Err(e),
         }
     }
+
+    /// Prints `prompt`, then returns the user's input, echoing `mask` for every character typed.
+    /// The input is returned in a buffer which is zeroed once it is dropped.
+    fn input_masked(&mut self, prompt: &str, mask: char) -> io::Result<Zeroizing<String>> {
+        write!(self, "{} ", prompt)?;
+        self.flush()?;
+
+        read_masked_line(self, mask)
+    }
+
+    fn message(&mut self, msg: &str) -> io::Result<()> {
+        writeln!(self, "{}", msg)?;
+        self.flush()
+    }
+}
+
+impl Input for Stdin {
+    /// Prints `prompt` to `Stdout`, then returns the user's input from `Stdin`.
+    fn input(&mut self, prompt: &str) -> io::Result<String> {
+        Terminal::new(self.lock(), io::stdout()).input(prompt)
+    }
+
+    /// Prints `prompt`, then returns the user's input without echoing it.
+    ///
+    /// Both the prompt and the input go through the terminal (`/dev/tty`), so that `Stdin` and
+    /// `Stdout` can be redirected while the user is still asked for the secret.
+    fn input_hidden(&mut self, prompt: &str) -> io::Result<String> {
+        Terminal::open()?.input_hidden(prompt)
+    }
+
+    /// Prints `prompt`, then returns the user's input, echoing `mask` for every character typed.
+    ///
+    /// Like `input_hidden`, this goes through the terminal rather than `Stdin` and `Stdout`.
+    fn input_masked(&mut self, prompt: &str, mask: char) -> io::Result<Zeroizing<String>> {
+        Terminal::open()?.input_masked(prompt, mask)
+    }
+
+    /// Prints `msg` to `Stdout`.
+    fn message(&mut self, msg: &str) -> io::Result<()> {
+        println!("{}", msg);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdin_extras::terminal::FakeTerminal;
+
+    #[test]
+    fn test_input() {
+        let mut term = FakeTerminal::fake("  foo bar \nbaz\n");
+
+        assert_eq!(term.input("First:").unwrap(), "foo bar");
+        assert_eq!(term.input("Second:").unwrap(), "baz");
+        assert_eq!(term.input("Third:").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(term.output(), "First: Second: Third: ");
+    }
+
+    #[test]
+    fn test_input_hidden() {
+        let mut term = FakeTerminal::fake("hunter2\n");
+
+        assert_eq!(term.input_hidden("Key:").unwrap(), "hunter2");
+        assert_eq!(term.output(), "Key: ");
+    }
+
+    #[test]
+    fn test_input_masked() {
+        let mut term = FakeTerminal::fake("hunter23\x7f\n");
+
+        assert_eq!(*term.input_masked("Key:", '*').unwrap(), "hunter2");
+        assert_eq!(term.output(), "Key: \x1b[?2004h********\x08 \x08\x1b[?2004l\n");
+    }
+
+    #[test]
+    fn test_input_masked_editing() {
+        let mut term = FakeTerminal::fake("oops\x15one two\x17three\n");
+        assert_eq!(*term.input_masked("Key:", '*').unwrap(), "one three");
+
+        let mut term = FakeTerminal::fake("ab\x1b[D\x1b[200~c\nd\x1b[201~e\n");
+        assert_eq!(*term.input_masked("Key:", '*').unwrap(), "abcde");
+
+        let mut term = FakeTerminal::fake("p\u{e4}ss\r");
+        assert_eq!(*term.input_masked("Key:", '#').unwrap(), "p\u{e4}ss");
+        assert!(term.output().contains("####\x1b"));
+    }
+
+    #[test]
+    fn test_input_parse() {
+        let mut term = FakeTerminal::fake("twelve\n12\n");
+
+        assert_eq!(term.input_parse::<u32>("Number:").unwrap(), 12);
+        assert_eq!(
+            term.output(),
+            "Number: Invalid input: invalid digit found in string\nNumber: "
+        );
+
+        let mut term = FakeTerminal::fake("x\n");
+        assert!(term.input_parse::<u32>("Number:").is_err());
+    }
+
+    #[test]
+    fn test_input_validated() {
+        let mut term = FakeTerminal::fake("ab\nabcd\n");
+        let val = term.input_validated("Name:", |s| match s.len() {
+            l if l < 3 => Err(String::from("Too short.")),
+            _ => Ok(()),
+        });
+
+        assert_eq!(val.unwrap(), "abcd");
+        assert_eq!(term.output(), "Name: Too short.\nName: ");
+    }
+
+    #[test]
+    fn test_confirm() {
+        let mut term = FakeTerminal::fake("\nYes\n");
+        assert!(!term.confirm("Overwrite?", Some(false)).unwrap());
+        assert!(term.confirm("Overwrite?", Some(false)).unwrap());
+        assert_eq!(term.output(), "Overwrite? [y/N] Overwrite? [y/N] ");
+
+        let mut term = FakeTerminal::fake("\nmaybe\nn\n");
+        assert!(!term.confirm("Overwrite?", None).unwrap());
+        assert_eq!(
+            term.output(),
+            "Overwrite? [y/n] Please answer 'yes' or 'no'.\n\
+             Overwrite? [y/n] Please answer 'yes' or 'no'.\n\
+             Overwrite? [y/n] "
+        );
+    }
+
+    #[test]
+    fn test_choose() {
+        let choices = ["keep", "overwrite", "rename"];
+
+        let mut term = FakeTerminal::fake("4\n2\n");
+        assert_eq!(term.choose("Action?", &choices, None).unwrap(), 1);
+        assert_eq!(
+            term.output(),
+            "  1) keep\n  2) overwrite\n  3) rename\n\
+             Action? [1-3] Please pick a number from 1 to 3.\nAction? [1-3] "
+        );
+
+        let mut term = FakeTerminal::fake("\nRENAME\n");
+        assert_eq!(term.choose("Action?", &choices, Some(0)).unwrap(), 0);
+        assert_eq!(term.choose("Action?", &choices, Some(0)).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_input_masked_eof() {
+        let mut term = FakeTerminal::fake("\x04");
+        assert!(term.input_masked("Key:", '*').is_err());
+
+        let mut term = FakeTerminal::fake("abc");
+        assert!(term.input_masked("Key:", '*').is_err());
+    }
 }
@@ -0,0 +1,196 @@
+use std::io::{self, BufRead, Read, Write};
+
+use crate::secret::Zeroizing;
+use crate::stdin_extras::terminal::Terminal;
+
+const BRACKETED_PASTE_ON: &[u8] = b"\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &[u8] = b"\x1b[?2004l";
+const PASTE_START: &[u8] = b"[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+const CTRL_D: u8 = 0x04;
+const CTRL_H: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const CTRL_W: u8 = 0x17;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// Reads a line from `term`, printing `mask` once for every character typed instead of the
+/// character itself.
+///
+/// The terminal is put into raw mode for the duration of the read, so the line editing is done
+/// here: backspace removes the last character, Ctrl-U the whole line and Ctrl-W the last word.
+/// Pasted text is accepted as is, without treating newlines in it as the end of the line.
+pub fn read_masked_line<R: BufRead, W: Write>(
+    term: &mut Terminal<R, W>,
+    mask: char,
+) -> io::Result<Zeroizing<String>> {
+    use libc::{ECHO, ICANON, IEXTEN, VMIN, VTIME};
+
+    let guard = term.guard(|termios| {
+        termios.c_lflag &= !(ECHO | ICANON | IEXTEN);
+        termios.c_cc[VMIN] = 1;
+        termios.c_cc[VTIME] = 0;
+    })?;
+
+    term.write_all(BRACKETED_PASTE_ON)?;
+    term.flush()?;
+
+    let res = MaskedReader::new(term, mask).read_line();
+
+    term.write_all(BRACKETED_PASTE_OFF)?;
+    term.write_all(b"\n")?;
+    term.flush()?;
+    if let Some(guard) = guard {
+        guard.restore()?;
+    }
+
+    res
+}
+
+struct MaskedReader<'a, T> {
+    tty: &'a mut T,
+    mask: String,
+    line: Zeroizing<String>,
+    pasting: bool,
+}
+
+impl<'a, T: Read + Write> MaskedReader<'a, T> {
+    fn new(tty: &'a mut T, mask: char) -> Self {
+        Self {
+            tty,
+            mask: mask.to_string(),
+            line: Zeroizing::new(String::with_capacity(128)),
+            pasting: false,
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        match self.tty.read(&mut byte)? {
+            0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the terminal was closed")),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    fn echo(&mut self, s: &str) -> io::Result<()> {
+        self.tty.write_all(s.as_bytes())?;
+        self.tty.flush()
+    }
+
+    fn read_line(mut self) -> io::Result<Zeroizing<String>> {
+        loop {
+            let byte = self.read_byte()?;
+
+            if self.pasting {
+                self.read_pasted(byte)?;
+                continue;
+            }
+
+            match byte {
+                b'\r' | b'\n' => return Ok(self.line),
+                CTRL_D if self.line.is_empty() => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no input"))
+                }
+                DEL | CTRL_H => self.erase(1)?,
+                CTRL_U => self.erase(self.line.chars().count())?,
+                CTRL_W => {
+                    let chars = self.line.chars().rev().collect::<Vec<char>>();
+                    let spaces = chars.iter().take_while(|c| c.is_whitespace()).count();
+                    let word = chars[spaces..].iter().take_while(|c| !c.is_whitespace()).count();
+                    self.erase(spaces + word)?;
+                }
+                ESC => self.read_escape()?,
+                b if b < 0x20 => {}
+                b => {
+                    let c = self.read_char(b)?;
+                    self.push(c)?;
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, c: char) -> io::Result<()> {
+        self.line.push_secure(c);
+        let mask = self.mask.clone();
+        self.echo(&mask)
+    }
+
+    fn erase(&mut self, n: usize) -> io::Result<()> {
+        for _ in 0..n {
+            if self.line.pop().is_some() {
+                self.echo("\x08 \x08")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of a UTF-8 encoded character starting with `first`.
+    fn read_char(&mut self, first: u8) -> io::Result<char> {
+        let len = match first {
+            b if b < 0x80 => 1,
+            b if b >> 5 == 0b110 => 2,
+            b if b >> 4 == 0b1110 => 3,
+            b if b >> 3 == 0b11110 => 4,
+            _ => return Ok(char::REPLACEMENT_CHARACTER),
+        };
+
+        let mut bytes = Zeroizing::new(vec![first]);
+        for _ in 1..len {
+            let b = self.read_byte()?;
+            bytes.push_secure(&[b]);
+        }
+
+        let c = std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        Ok(c)
+    }
+
+    /// Handles an escape sequence. The start of a bracketed paste is recognised, anything else
+    /// (like the arrow keys) is ignored.
+    fn read_escape(&mut self) -> io::Result<()> {
+        let mut seq = Vec::new();
+
+        loop {
+            let b = self.read_byte()?;
+            seq.push(b);
+
+            if PASTE_START.starts_with(&seq) {
+                if seq == PASTE_START {
+                    self.pasting = true;
+                    return Ok(());
+                }
+                continue;
+            }
+            // CSI sequences end with a byte in `@..~`, other escapes are a single byte.
+            if seq[0] != b'[' || (seq.len() > 1 && (0x40..=0x7e).contains(&b)) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_pasted(&mut self, first: u8) -> io::Result<()> {
+        if first == ESC {
+            let mut seq = vec![ESC];
+            while PASTE_END.starts_with(&seq) && seq != PASTE_END {
+                seq.push(self.read_byte()?);
+            }
+
+            if seq == PASTE_END {
+                self.pasting = false;
+            }
+            return Ok(());
+        }
+
+        match first {
+            b'\r' | b'\n' => Ok(()),
+            b if b < 0x20 || b == DEL => Ok(()),
+            b => {
+                let c = self.read_char(b)?;
+                self.push(c)
+            }
+        }
+    }
+}
@@ -1,7 +1,11 @@
-//! Implements some custom traits for `Stdin` to make reading console input simpler.
+//! Implements some custom traits for `Stdin` and other terminals to make reading console input
+//! simpler.
 
 pub mod input;
+pub mod masked;
 pub mod read_hidden;
+pub mod terminal;
 
 pub use input::Input;
 pub use read_hidden::ReadHidden;
+pub use terminal::{FakeTerminal, Terminal, Tty};
@@ -1,50 +1,162 @@
 use libc::termios;
-use std::io::{self, Stdin};
+use std::io::{self, BufRead, Stdin, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::stdin_extras::terminal::Terminal;
 
 /// Provides the `read_hidden_line` method to implementors.
 pub trait ReadHidden {
-    fn read_hidden_line(&self, buf: &mut String) -> io::Result<usize>;
+    fn read_hidden_line(&mut self, buf: &mut String) -> io::Result<usize>;
 }
 
-fn get_termios_attr() -> termios {
-    let mut termios = termios {
-        c_iflag: 0,
-        c_oflag: 0,
-        c_cflag: 0,
-        c_lflag: 0,
-        c_line: 0,
-        c_cc: [0; 32],
-        c_ispeed: 0,
-        c_ospeed: 0,
-    };
+fn get_termios_attr(fd: RawFd) -> io::Result<termios> {
+    let mut termios = unsafe { std::mem::zeroed::<termios>() };
 
-    unsafe {
-        libc::tcgetattr(libc::STDIN_FILENO, &mut termios);
+    match unsafe { libc::tcgetattr(fd, &mut termios) } {
+        0 => Ok(termios),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn set_termios_attr(fd: RawFd, termios: &termios) -> io::Result<()> {
+    match unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
     }
-    termios
 }
 
-fn set_termios_attr(termios: &termios) {
+// The terminal attributes to restore if the process is interrupted while a guard is alive. They
+// live in statics because a signal handler cannot safely take a lock or allocate.
+static GUARDED_FD: AtomicI32 = AtomicI32::new(-1);
+static mut GUARDED_TERMIOS: MaybeUninit<termios> = MaybeUninit::uninit();
+
+const GUARDED_SIGNALS: [libc::c_int; 4] =
+    [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    let fd = GUARDED_FD.swap(-1, Ordering::SeqCst);
+
     unsafe {
-        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+        if fd >= 0 {
+            libc::tcsetattr(fd, libc::TCSANOW, (*ptr::addr_of!(GUARDED_TERMIOS)).as_ptr());
+        }
+
+        // Re-raise with the default action so the process still dies from the signal, and the
+        // parent sees why.
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
     }
 }
 
-impl ReadHidden for Stdin {
-    /// Calls `Stdin::read_line`, but prevents any input charactes from bring echoed to `Stdout`.
-    fn read_hidden_line(&self, buf: &mut String) -> io::Result<usize> {
-        use libc::{ECHO, ECHONL};
+/// Changes the attributes of a terminal, and restores the original ones when dropped.
+///
+/// The original attributes are also restored if the process is killed by `SIGINT`, `SIGTERM`,
+/// `SIGHUP` or `SIGQUIT` while the guard is alive, so that e.g. pressing Ctrl-C at a hidden
+/// prompt does not leave the shell without echo. Only one guard should be alive at a time.
+pub struct TermiosGuard {
+    fd: RawFd,
+    original: termios,
+    old_actions: Vec<(libc::c_int, libc::sigaction)>,
+    active: bool,
+}
+
+impl TermiosGuard {
+    /// Saves the current attributes of the terminal `fd`, and applies the ones returned by
+    /// `change`.
+    pub fn new<F: FnOnce(&mut termios)>(fd: RawFd, change: F) -> io::Result<Self> {
+        let original = get_termios_attr(fd)?;
+
+        unsafe {
+            ptr::addr_of_mut!(GUARDED_TERMIOS).write(MaybeUninit::new(original));
+        }
+        GUARDED_FD.store(fd, Ordering::SeqCst);
+
+        let mut guard = Self {
+            fd,
+            original,
+            old_actions: Vec::new(),
+            active: true,
+        };
+
+        for signal in GUARDED_SIGNALS.iter() {
+            unsafe {
+                let mut action = std::mem::zeroed::<libc::sigaction>();
+                let mut old = std::mem::zeroed::<libc::sigaction>();
+
+                action.sa_sigaction = restore_and_reraise as extern "C" fn(libc::c_int) as usize;
+                libc::sigemptyset(&mut action.sa_mask);
+
+                if libc::sigaction(*signal, &action, &mut old) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                guard.old_actions.push((*signal, old));
+            }
+        }
+
+        let mut termios = original;
+        change(&mut termios);
+        set_termios_attr(fd, &termios)?;
 
-        let termios_orig = get_termios_attr();
-        let mut termios = termios_orig.clone();
+        Ok(guard)
+    }
+
+    /// Restores the original attributes, reporting any error that `Drop` would have to ignore.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        self.active = false;
+
+        let res = set_termios_attr(self.fd, &self.original);
+        GUARDED_FD.store(-1, Ordering::SeqCst);
 
-        termios.c_lflag &= !ECHO;
-        termios.c_lflag |= ECHONL;
+        for (signal, old) in self.old_actions.drain(..) {
+            unsafe {
+                libc::sigaction(signal, &old, ptr::null_mut());
+            }
+        }
+
+        res
+    }
+}
+
+impl Drop for TermiosGuard {
+    fn drop(&mut self) {
+        if self.active {
+            let _ = self.release();
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> ReadHidden for Terminal<R, W> {
+    /// Reads a line from the terminal, but prevents any input characters from being echoed.
+    fn read_hidden_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        use libc::{ECHO, ECHONL};
+
+        let guard = self.guard(|termios| {
+            termios.c_lflag &= !ECHO;
+            termios.c_lflag |= ECHONL;
+        })?;
 
-        set_termios_attr(&termios);
         let res = self.read_line(buf);
-        set_termios_attr(&termios_orig);
+        if let Some(guard) = guard {
+            guard.restore()?;
+        }
 
         res
     }
 }
+
+impl ReadHidden for Stdin {
+    /// Reads a line from the terminal, but prevents any input characters from being echoed.
+    ///
+    /// The line is read from `/dev/tty` rather than from `Stdin` itself, so that a secret can
+    /// still be typed in when `Stdin` is redirected from a file or a pipe.
+    fn read_hidden_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        Terminal::open()?.read_hidden_line(buf)
+    }
+}
@@ -0,0 +1,232 @@
+use libc::termios;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use crate::stdin_extras::read_hidden::TermiosGuard;
+
+/// A terminal, made of a reader for its input and a writer for its output.
+///
+/// If the terminal is backed by a real terminal device, its file descriptor is used to change
+/// attributes like echo while reading secrets. Without one, the terminal is just a pair of
+/// streams, which is what pipes and the in-memory `FakeTerminal` are.
+pub struct Terminal<R, W> {
+    reader: R,
+    writer: W,
+    fd: Option<RawFd>,
+}
+
+/// An in-memory terminal, to test prompts without a user sitting in front of them.
+pub type FakeTerminal = Terminal<Cursor<Vec<u8>>, Vec<u8>>;
+
+/// The controlling terminal of the process.
+pub type Tty = Terminal<BufReader<TimeoutReader<File>>, File>;
+
+/// Wraps a reader backed by a file descriptor, so that reads fail with `TimedOut` if no input
+/// arrives within the timeout.
+pub struct TimeoutReader<T> {
+    inner: T,
+    timeout: Option<Duration>,
+}
+
+impl<T: Read + AsRawFd> TimeoutReader<T> {
+    /// Wraps `inner`. If `timeout` is `None`, reads wait for input forever.
+    pub fn new(inner: T, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+
+    fn wait(&self, timeout: Duration) -> io::Result<()> {
+        let mut fd = libc::pollfd {
+            fd: self.inner.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+        loop {
+            match unsafe { libc::poll(&mut fd, 1, ms) } {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "timed out waiting for input",
+                    ))
+                }
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<T: Read + AsRawFd> Read for TimeoutReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(timeout) = self.timeout {
+            self.wait(timeout)?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<R: BufRead, W: Write> Terminal<R, W> {
+    /// Creates a terminal without a terminal device, whose attributes are never changed.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            fd: None,
+        }
+    }
+
+    /// Creates a terminal whose attributes are changed through `tty`.
+    pub fn with_tty<T: AsRawFd>(reader: R, writer: W, tty: &T) -> Self {
+        Self {
+            reader,
+            writer,
+            fd: Some(tty.as_raw_fd()),
+        }
+    }
+
+    /// Changes the attributes of the terminal device until the returned guard is dropped or
+    /// restored. Does nothing if there is no terminal device.
+    pub fn guard<F: FnOnce(&mut termios)>(&self, change: F) -> io::Result<Option<TermiosGuard>> {
+        match self.fd {
+            Some(fd) => TermiosGuard::new(fd, change).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn reader(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_parts(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl Tty {
+    /// Opens the controlling terminal of the process, so that it can be used even if `Stdin`
+    /// and `Stdout` have been redirected.
+    pub fn open() -> io::Result<Self> {
+        Self::open_with_timeout(None)
+    }
+
+    /// Like `open`, but reading from the terminal fails if the user does not type anything
+    /// within `timeout`.
+    pub fn open_with_timeout(timeout: Option<Duration>) -> io::Result<Self> {
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("no terminal available to read hidden input from ({})", e),
+                )
+            })?;
+        let writer = tty.try_clone()?;
+
+        Ok(Self {
+            fd: Some(tty.as_raw_fd()),
+            reader: BufReader::new(TimeoutReader::new(tty, timeout)),
+            writer,
+        })
+    }
+}
+
+impl FakeTerminal {
+    /// Creates a fake terminal which will read `input`, as if it had been typed in.
+    pub fn fake(input: &str) -> Self {
+        Self::new(Cursor::new(input.as_bytes().to_vec()), Vec::new())
+    }
+
+    /// Returns everything written to the terminal so far.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.writer).into_owned()
+    }
+}
+
+impl<R: BufRead, W: Write> Read for Terminal<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: BufRead, W: Write> BufRead for Terminal<R, W> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+impl<R: BufRead, W: Write> Write for Terminal<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::FromRawFd;
+
+    /// Returns both ends of a new pipe.
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (read, _write) = pipe();
+        let mut reader = TimeoutReader::new(read, Some(Duration::from_millis(20)));
+
+        let e = reader.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_input_in_time() {
+        let (read, mut write) = pipe();
+        let mut reader = TimeoutReader::new(read, Some(Duration::from_secs(10)));
+        write.write_all(b"key\n").unwrap();
+
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"key\n");
+
+        // A closed writer is the end of the input, not a timeout.
+        drop(write);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_no_timeout() {
+        let (read, mut write) = pipe();
+        let mut reader = BufReader::new(TimeoutReader::new(read, None));
+        write.write_all(b"first\nsecond\n").unwrap();
+        drop(write);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "first\n");
+    }
+}
@@ -0,0 +1,754 @@
+//! A small password strength estimator in the spirit of Dropbox's zxcvbn.
+//!
+//! The password is scanned for patterns that an attacker would try before falling back to
+//! brute force: common passwords and words (also reversed or written in l33t), keyboard walks,
+//! character sequences, repeats and years. The cheapest way to cover the whole password with
+//! those patterns gives an estimate of how many guesses are needed to find it.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::secret::Zeroizing;
+
+const MIN_YEAR_SPACE: i32 = 20;
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 10000.0;
+const MAX_LENGTH: usize = 100;
+/// How long the base of a repeat can be, which keeps looking for repeats from growing too slow
+/// on long keys.
+const MAX_REPEAT_BASE: usize = 20;
+
+/// The result of estimating the strength of a password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    /// The base 10 logarithm of the estimated number of guesses needed to find the password.
+    pub guesses_log10: f64,
+    /// A score from `0` (trivially guessable) to `4` (very unguessable).
+    pub score: u8,
+    /// Explains what makes the password weak, if anything in particular does.
+    pub warning: Option<&'static str>,
+    /// Hints on how to pick a stronger password.
+    pub suggestions: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pattern {
+    Dictionary { rank: usize, reversed: bool, l33t: bool },
+    Spatial,
+    Sequence,
+    Repeat,
+    Year,
+    Bruteforce,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    i: usize,
+    j: usize,
+    pattern: Pattern,
+    guesses_log10: f64,
+}
+
+/// Estimates how hard `password` is to guess.
+pub fn estimate(password: &str) -> Estimate {
+    // The copies of the password are made in buffers which never grow, and are zeroed after.
+    let mut chars = Zeroizing::new(Vec::with_capacity(MAX_LENGTH));
+    chars.extend(password.chars().take(MAX_LENGTH));
+
+    if chars.is_empty() {
+        return Estimate {
+            guesses_log10: 0.0,
+            score: 0,
+            warning: Some("An empty key can be guessed instantly."),
+            suggestions: vec!["Use a few words, avoid common phrases."],
+        };
+    }
+
+    let mut matches = pattern_matches(&chars);
+    repeat_matches(&chars, &mut matches);
+
+    let (guesses_log10, sequence) = most_guessable_sequence(&chars, &matches);
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    let (warning, suggestions) = feedback(score, &chars, &sequence);
+
+    Estimate {
+        guesses_log10,
+        score,
+        warning,
+        suggestions,
+    }
+}
+
+/// Finds every match of a pattern in `chars`, except for repeats.
+fn pattern_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    dictionary_matches(chars, &mut matches);
+    spatial_matches(chars, &mut matches);
+    sequence_matches(chars, &mut matches);
+    year_matches(chars, &mut matches);
+    matches
+}
+
+/// Finds the sequence of non-overlapping matches covering the whole password which needs the
+/// fewest guesses, filling any gaps with brute force.
+fn most_guessable_sequence(chars: &[char], matches: &[Match]) -> (f64, Vec<Match>) {
+    let n = chars.len();
+
+    // best[k][l] holds the cheapest way (in log10 guesses) to cover `chars[..k]` with `l`
+    // matches, along with the last match used to get there.
+    let mut best: Vec<HashMap<usize, (f64, Match)>> = vec![HashMap::new(); n + 1];
+
+    let mut by_end: Vec<Vec<Match>> = vec![Vec::new(); n];
+    for m in matches {
+        by_end[m.j].push(*m);
+    }
+
+    for k in 0..n {
+        let mut candidates = by_end[k].clone();
+        for i in 0..=k {
+            candidates.push(bruteforce_match(i, k));
+        }
+
+        for m in candidates {
+            if m.i == 0 {
+                extend(&mut best[k + 1], 1, m.guesses_log10, m);
+                continue;
+            }
+
+            let prev = best[m.i].iter().map(|(l, v)| (*l, v.0)).collect::<Vec<_>>();
+            for (l, prev_guesses) in prev {
+                // Two brute force segments in a row are only ever worse than a single one.
+                if let Pattern::Bruteforce = m.pattern {
+                    if let Pattern::Bruteforce = best[m.i][&l].1.pattern {
+                        continue;
+                    }
+                }
+                extend(&mut best[k + 1], l + 1, prev_guesses + m.guesses_log10, m);
+            }
+        }
+    }
+
+    let mut total = f64::INFINITY;
+    let mut count = 1;
+    for (l, (product, _)) in &best[n] {
+        // zxcvbn: guesses = l! * product + D^(l - 1)
+        let guesses = log10_sum(
+            log10_factorial(*l) + product,
+            (*l as f64 - 1.0) * MIN_GUESSES_BEFORE_GROWING_SEQUENCE.log10(),
+        );
+        if guesses < total {
+            total = guesses;
+            count = *l;
+        }
+    }
+
+    let mut sequence = Vec::new();
+    let mut k = n;
+    while count > 0 {
+        let m = best[k][&count].1;
+        sequence.push(m);
+        k = m.i;
+        count -= 1;
+    }
+    sequence.reverse();
+
+    (total, sequence)
+}
+
+fn extend(slot: &mut HashMap<usize, (f64, Match)>, l: usize, guesses: f64, m: Match) {
+    match slot.get(&l) {
+        Some((g, _)) if *g <= guesses => {}
+        _ => {
+            slot.insert(l, (guesses, m));
+        }
+    }
+}
+
+fn bruteforce_match(i: usize, j: usize) -> Match {
+    let len = (j - i + 1) as f64;
+    let min = if len == 1.0 { 11f64 } else { 51f64 };
+
+    Match {
+        i,
+        j,
+        pattern: Pattern::Bruteforce,
+        guesses_log10: (len * BRUTEFORCE_CARDINALITY.log10()).max(min.log10()),
+    }
+}
+
+fn min_guesses_log10(len: usize) -> f64 {
+    if len == 1 {
+        1f64
+    } else {
+        50f64.log10()
+    }
+}
+
+fn dictionary_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut ranked = HashMap::new();
+    for (rank, word) in PASSWORDS.iter().chain(WORDS.iter()).enumerate() {
+        ranked.entry(*word).or_insert(rank + 1);
+    }
+
+    // A character lowercases to at most 3.
+    let mut lower = Zeroizing::new(Vec::with_capacity(chars.len() * 3));
+    lower.extend(chars.iter().flat_map(|c| c.to_lowercase()));
+    if lower.len() != chars.len() {
+        return;
+    }
+    let mut unleeted = Zeroizing::new(Vec::with_capacity(chars.len()));
+    unleeted.extend(lower.iter().map(|c| unleet(*c)));
+    let n = chars.len();
+
+    // Every span is written into the same buffers, which have room for the whole password.
+    let mut word = Zeroizing::new(String::with_capacity(n * 4));
+    let mut reversed = Zeroizing::new(String::with_capacity(n * 4));
+    let mut plain = Zeroizing::new(String::with_capacity(n * 4));
+
+    for i in 0..n {
+        for j in i..n {
+            let span = &chars[i..=j];
+
+            let mut try_word = |word: &str, reversed: bool, l33t: bool| {
+                if let Some(rank) = ranked.get(word) {
+                    let mut guesses = (*rank as f64).log10() + uppercase_variations(span);
+                    if reversed {
+                        guesses += 2f64.log10();
+                    }
+                    if l33t {
+                        guesses += l33t_variations(span);
+                    }
+                    matches.push(Match {
+                        i,
+                        j,
+                        pattern: Pattern::Dictionary {
+                            rank: *rank,
+                            reversed,
+                            l33t,
+                        },
+                        guesses_log10: guesses.max(min_guesses_log10(j - i + 1)),
+                    });
+                }
+            };
+
+            word.clear();
+            word.extend(&lower[i..=j]);
+            try_word(&word, false, false);
+            reversed.clear();
+            reversed.extend(lower[i..=j].iter().rev());
+            try_word(&reversed, true, false);
+
+            plain.clear();
+            plain.extend(&unleeted[i..=j]);
+            if *plain != *word {
+                try_word(&plain, false, true);
+            }
+        }
+    }
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '8' => 'b',
+        '(' | '{' | '[' | '<' => 'c',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' | '+' => 't',
+        '%' => 'x',
+        '2' => 'z',
+        c => c,
+    }
+}
+
+/// The number of ways the letters of a dictionary word could have been capitalised.
+fn uppercase_variations(span: &[char]) -> f64 {
+    let upper = span.iter().filter(|c| c.is_uppercase()).count();
+    let lower = span.iter().filter(|c| c.is_lowercase()).count();
+
+    if upper == 0 {
+        return 0.0;
+    }
+    if lower == 0 || (upper == 1 && span[0].is_uppercase()) {
+        return 2f64.log10();
+    }
+    if upper == 1 && span[span.len() - 1].is_uppercase() {
+        return 2f64.log10();
+    }
+
+    let mut variations = 0.0;
+    for i in 1..=upper.min(lower) {
+        variations += n_choose_k(upper + lower, i);
+    }
+    variations.log10()
+}
+
+/// The number of ways a word could have had letters swapped for l33t characters.
+fn l33t_variations(span: &[char]) -> f64 {
+    let substituted = span.iter().filter(|c| unleet(**c) != **c).count();
+    let len = span.len();
+
+    let mut variations = 0.0;
+    for i in 1..=substituted {
+        variations += n_choose_k(len, i);
+    }
+    variations.max(2.0).log10()
+}
+
+fn spatial_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let mut i = 0;
+
+    while i + 2 < n {
+        let mut j = i;
+        let mut turns = 0;
+        let mut shifted = 0;
+        let mut last_direction = None;
+
+        if is_shifted(chars[i]) {
+            shifted += 1;
+        }
+
+        while j + 1 < n {
+            match key_direction(chars[j], chars[j + 1]) {
+                Some(dir) => {
+                    if last_direction != Some(dir) {
+                        turns += 1;
+                        last_direction = Some(dir);
+                    }
+                    if is_shifted(chars[j + 1]) {
+                        shifted += 1;
+                    }
+                    j += 1;
+                }
+                None => break,
+            }
+        }
+
+        if j - i + 1 >= 3 {
+            matches.push(Match {
+                i,
+                j,
+                pattern: Pattern::Spatial,
+                guesses_log10: spatial_guesses(j - i + 1, turns, shifted),
+            });
+            i = j;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+fn spatial_guesses(len: usize, turns: usize, shifted: usize) -> f64 {
+    // Roughly the number of keys on a QWERTY keyboard and their average number of neighbours.
+    let starts = 94.0;
+    let degree = 4.6f64;
+
+    let mut guesses = 0.0;
+    for i in 2..=len {
+        for j in 1..=turns.min(i - 1) {
+            guesses += n_choose_k(i - 1, j - 1) * starts * degree.powi(j as i32);
+        }
+    }
+
+    let unshifted = len - shifted;
+    if shifted > 0 {
+        if unshifted == 0 {
+            guesses *= 2.0;
+        } else {
+            let mut variations = 0.0;
+            for i in 1..=shifted.min(unshifted) {
+                variations += n_choose_k(len, i);
+            }
+            guesses *= variations;
+        }
+    }
+
+    guesses.log10().max(min_guesses_log10(len))
+}
+
+const KEYBOARD: [&str; 4] = [
+    "`1234567890-=",
+    " qwertyuiop[]\\",
+    " asdfghjkl;'",
+    " zxcvbnm,./",
+];
+const KEYBOARD_SHIFTED: [&str; 4] = [
+    "~!@#$%^&*()_+",
+    " QWERTYUIOP{}|",
+    " ASDFGHJKL:\"",
+    " ZXCVBNM<>?",
+];
+
+fn key_position(c: char) -> Option<(i32, i32)> {
+    for (row, (lower, upper)) in KEYBOARD.iter().zip(KEYBOARD_SHIFTED.iter()).enumerate() {
+        if let Some(col) = lower.chars().position(|k| k == c && k != ' ') {
+            return Some((row as i32, col as i32));
+        }
+        if let Some(col) = upper.chars().position(|k| k == c && k != ' ') {
+            return Some((row as i32, col as i32));
+        }
+    }
+    None
+}
+
+fn is_shifted(c: char) -> bool {
+    KEYBOARD_SHIFTED.iter().any(|row| row.contains(c)) && c != ' '
+}
+
+/// Returns which neighbouring key `b` is relative to `a`, if they are neighbours at all.
+///
+/// Each row of a keyboard is staggered half a key to the right of the one above it, so the keys
+/// above a key are at the same and next column, and the keys below at the same and previous.
+fn key_direction(a: char, b: char) -> Option<(i32, i32)> {
+    let (ra, ca) = key_position(a)?;
+    let (rb, cb) = key_position(b)?;
+    let dir = (rb - ra, cb - ca);
+
+    match dir {
+        (0, -1) | (0, 1) | (-1, 0) | (-1, 1) | (1, 0) | (1, -1) => Some(dir),
+        _ => None,
+    }
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let mut i = 0;
+
+    while i + 2 < n {
+        let delta = chars[i + 1] as i64 - chars[i] as i64;
+        let mut j = i + 1;
+
+        if delta == 0 || delta.abs() > 5 {
+            i += 1;
+            continue;
+        }
+        while j + 1 < n && chars[j + 1] as i64 - chars[j] as i64 == delta {
+            j += 1;
+        }
+
+        if j - i + 1 >= 3 {
+            let first = chars[i];
+            let mut base: f64 = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            if delta < 0 {
+                base *= 2.0;
+            }
+
+            matches.push(Match {
+                i,
+                j,
+                pattern: Pattern::Sequence,
+                guesses_log10: (base * (j - i + 1) as f64 * delta.abs() as f64).log10(),
+            });
+            i = j;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+fn repeat_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    // The same base repeats at many positions, so each is only scored once, and without looking
+    // for repeats in it.
+    let mut scored = HashMap::new();
+
+    for i in 0..n {
+        for base_len in 1..=((n - i) / 2).min(MAX_REPEAT_BASE) {
+            let base = &chars[i..i + base_len];
+            let mut count = 1;
+            while i + (count + 1) * base_len <= n
+                && &chars[i + count * base_len..i + (count + 1) * base_len] == base
+            {
+                count += 1;
+            }
+
+            if count > 1 && count * base_len >= 3 {
+                let base_guesses = *scored.entry(base).or_insert_with(|| match base_len {
+                    1 => cardinality(base[0]).log10(),
+                    _ => most_guessable_sequence(base, &pattern_matches(base)).0,
+                });
+
+                matches.push(Match {
+                    i,
+                    j: i + count * base_len - 1,
+                    pattern: Pattern::Repeat,
+                    guesses_log10: base_guesses + (count as f64).log10(),
+                });
+            }
+        }
+    }
+}
+
+fn cardinality(c: char) -> f64 {
+    if c.is_ascii_digit() {
+        10.0
+    } else if c.is_ascii_lowercase() || c.is_ascii_uppercase() {
+        26.0
+    } else if c.is_ascii() {
+        33.0
+    } else {
+        100.0
+    }
+}
+
+/// Returns the current year, which years are guessed outwards from.
+fn current_year() -> i32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // The average length of a Gregorian year in seconds.
+    1970 + (secs / 31_556_952) as i32
+}
+
+fn year_matches(chars: &[char], matches: &mut Vec<Match>) {
+    if chars.len() < 4 {
+        return;
+    }
+    let current_year = current_year();
+
+    for i in 0..=chars.len() - 4 {
+        let digits = &chars[i..i + 4];
+        if !digits.iter().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let year = digits
+            .iter()
+            .fold(0, |year, c| year * 10 + c.to_digit(10).unwrap() as i32);
+        if (1900..=2050).contains(&year) {
+            let space = (year - current_year).abs().max(MIN_YEAR_SPACE);
+            matches.push(Match {
+                i,
+                j: i + 3,
+                pattern: Pattern::Year,
+                guesses_log10: (space as f64).log10(),
+            });
+        }
+    }
+}
+
+fn feedback(score: u8, chars: &[char], sequence: &[Match]) -> (Option<&'static str>, Vec<&'static str>) {
+    let mut suggestions = Vec::new();
+
+    if score > 2 {
+        return (None, suggestions);
+    }
+
+    suggestions.push("Add another word or two. Uncommon words are better.");
+
+    let longest = sequence
+        .iter()
+        .filter(|m| m.pattern != Pattern::Bruteforce)
+        .max_by_key(|m| m.j - m.i);
+    let single = sequence.len() == 1;
+
+    let warning = match longest.map(|m| m.pattern) {
+        Some(Pattern::Dictionary { rank, reversed, l33t }) => {
+            if reversed {
+                suggestions.push("Reversed words aren't much harder to guess.");
+            }
+            if l33t {
+                suggestions.push("Predictable substitutions like '@' instead of 'a' don't help very much.");
+            }
+            if chars.iter().any(|c| c.is_uppercase()) {
+                suggestions.push("Capitalization doesn't help very much.");
+            }
+
+            if single && rank <= 10 {
+                Some("This is a top-10 common password.")
+            } else if single && rank <= 100 {
+                Some("This is a top-100 common password.")
+            } else if single && rank <= PASSWORDS.len() {
+                Some("This is a very common password.")
+            } else if single {
+                Some("A word by itself is easy to guess.")
+            } else {
+                Some("Common words and passwords are easy to guess.")
+            }
+        }
+        Some(Pattern::Spatial) => {
+            suggestions.push("Use a longer keyboard pattern with more turns.");
+            Some("Straight rows of keys are easy to guess.")
+        }
+        Some(Pattern::Sequence) => {
+            suggestions.push("Avoid sequences.");
+            Some("Sequences like abc or 6543 are easy to guess.")
+        }
+        Some(Pattern::Repeat) => {
+            suggestions.push("Avoid repeated words and characters.");
+            Some("Repeats like \"aaa\" or \"abcabc\" are easy to guess.")
+        }
+        Some(Pattern::Year) => {
+            suggestions.push("Avoid years that are associated with you.");
+            Some("Recent years are easy to guess.")
+        }
+        Some(Pattern::Bruteforce) | None => {
+            if chars.len() < 8 {
+                Some("Short keys are easy to guess.")
+            } else {
+                None
+            }
+        }
+    };
+
+    (warning, suggestions)
+}
+
+fn n_choose_k(n: usize, k: usize) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+
+    let mut r = 1.0;
+    for d in 1..=k {
+        r *= (n - k + d) as f64;
+        r /= d as f64;
+    }
+    r
+}
+
+fn log10_factorial(n: usize) -> f64 {
+    (2..=n).map(|i| (i as f64).log10()).sum()
+}
+
+fn log10_sum(a: f64, b: f64) -> f64 {
+    let (hi, lo) = if a > b { (a, b) } else { (b, a) };
+    hi + (1.0 + 10f64.powf(lo - hi)).log10()
+}
+
+/// Common passwords, most common first.
+const PASSWORDS: &[&str] = &[
+    "123456", "password", "12345678", "qwerty", "123456789", "12345", "1234", "111111",
+    "1234567", "dragon", "123123", "baseball", "abc123", "football", "monkey", "letmein",
+    "696969", "shadow", "master", "666666", "qwertyuiop", "123321", "mustang", "1234567890",
+    "michael", "654321", "superman", "1qaz2wsx", "7777777", "121212", "000000", "qazwsx",
+    "123qwe", "killer", "trustno1", "jordan", "jennifer", "zxcvbnm", "asdfgh", "hunter",
+    "buster", "soccer", "harley", "batman", "andrew", "tigger", "sunshine", "iloveyou",
+    "2000", "charlie", "robert", "thomas", "hockey", "ranger", "daniel", "starwars", "klaster",
+    "112233", "george", "computer", "michelle", "jessica", "pepper", "1111", "zxcvbn", "555555",
+    "11111111", "131313", "freedom", "777777", "pass", "maggie", "159753", "aaaaaa", "ginger",
+    "princess", "joshua", "cheese", "amanda", "summer", "love", "ashley", "nicole", "chelsea",
+    "biteme", "matthew", "access", "yankees", "987654321", "dallas", "austin", "thunder",
+    "taylor", "matrix", "william", "corvette", "hello", "martin", "heather", "secret", "merlin",
+    "diamond", "1234qwer", "gfhjkm", "hammer", "silver", "222222", "88888888", "anthony",
+    "justin", "test", "bailey", "q1w2e3r4t5", "patrick", "internet", "scooter", "orange",
+    "11111", "golfer", "cookie", "richard", "samantha", "bigdog", "guitar", "jackson",
+    "whatever", "mickey", "chicken", "sparky", "snoopy", "maverick", "phoenix", "camaro",
+    "peanut", "morgan", "welcome", "falcon", "cowboy", "ferrari", "samsung", "andrea",
+    "smokey", "steelers", "joseph", "mercedes", "dakota", "arsenal", "eagles", "melissa",
+    "boomer", "booboo", "spider", "nascar", "monster", "tigers", "yellow", "xxxxxx",
+    "123123123", "gateway", "marina", "diablo", "bulldog", "qwer1234", "compaq", "purple",
+    "hardcore", "banana", "junior", "hannah", "123654", "porsche", "lakers", "iceman", "money",
+    "cowboys", "987654", "london", "tennis", "999999", "ncc1701", "coffee", "scooby", "0000",
+    "miller", "boston", "q1w2e3r4", "fuckoff", "brandon", "yamaha", "chester", "mother",
+    "forever", "johnny", "edward", "333333", "oliver", "redsox", "player", "nikita", "knight",
+    "fender", "barney", "midnight", "please", "brandy", "chicago", "badboy", "iwantu", "slayer",
+    "rangers", "charles", "angel", "flower", "bigdaddy", "rabbit", "wizard", "bigdick",
+    "jasper", "enter", "rachel", "chris", "steven", "winner", "adidas", "victoria", "natasha",
+    "1q2w3e4r", "jasmine", "winter", "prince", "panties", "marine", "ghbdtn", "fishing", "cocacola",
+    "casper", "james", "232323", "raiders", "888888", "marlboro", "gandalf", "asdfasdf",
+    "crystal", "87654321", "12344321", "golden", "8675309", "dexter", "admin", "changeme",
+    "passw0rd", "p@ssw0rd", "letmein1", "password1", "password123", "welcome1", "qwerty123",
+    "1q2w3e", "abcdef", "abcd1234", "crypt", "secret123",
+];
+
+/// Common English words and names.
+const WORDS: &[&str] = &[
+    "the", "be", "to", "of", "and", "a", "in", "that", "have", "i", "it", "for", "not", "on",
+    "with", "he", "as", "you", "do", "at", "this", "but", "his", "by", "from", "they", "we",
+    "say", "her", "she", "or", "an", "will", "my", "one", "all", "would", "there", "their",
+    "what", "so", "up", "out", "if", "about", "who", "get", "which", "go", "me", "when", "make",
+    "can", "like", "time", "no", "just", "him", "know", "take", "people", "into", "year",
+    "your", "good", "some", "could", "them", "see", "other", "than", "then", "now", "look",
+    "only", "come", "its", "over", "think", "also", "back", "after", "use", "two", "how", "our",
+    "work", "first", "well", "way", "even", "new", "want", "because", "any", "these", "give",
+    "day", "most", "us", "is", "are", "was", "were", "been", "has", "had", "did", "said",
+    "man", "woman", "child", "world", "life", "hand", "part", "place", "case", "week",
+    "company", "system", "program", "question", "government", "number", "night", "point",
+    "home", "water", "room", "mother", "area", "money", "story", "fact", "month", "lot",
+    "right", "study", "book", "eye", "job", "word", "business", "issue", "side", "kind",
+    "head", "house", "service", "friend", "father", "power", "hour", "game", "line", "end",
+    "member", "law", "car", "city", "community", "name", "president", "team", "minute", "idea",
+    "kid", "body", "information", "school", "face", "others", "level", "office", "door",
+    "health", "person", "art", "war", "history", "party", "result", "change", "morning",
+    "reason", "research", "girl", "guy", "moment", "air", "teacher", "force", "education",
+    "apple", "orange", "banana", "horse", "battery", "staple", "correct", "dog", "cat", "fish",
+    "bird", "red", "blue", "green", "black", "white", "sun", "moon", "star", "fire", "ice",
+    "love", "key", "lock", "door", "open", "close", "secret", "hidden", "private", "crypto",
+    "file", "data", "summer", "winter", "spring", "autumn", "monday", "friday", "sunday",
+    "january", "december", "happy", "family", "music", "dragon", "tiger", "lion", "bear",
+    "wolf", "eagle", "king", "queen", "prince", "princess", "angel", "devil", "heaven", "hell",
+    "john", "mary", "james", "robert", "linda", "david", "susan", "sarah", "paul", "mark",
+    "peter", "anna", "emma", "alex", "sam", "max", "ben", "jack", "lucy", "kate",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_passwords() {
+        for p in &["password", "123456", "qwerty", "letmein", "P@ssw0rd"] {
+            let e = estimate(p);
+            assert_eq!(e.score, 0, "{}: {:?}", p, e);
+            assert!(e.warning.is_some());
+        }
+    }
+
+    #[test]
+    fn test_years() {
+        // Years are guessed outwards from the current one.
+        let this_year = estimate(&current_year().to_string()).guesses_log10;
+        assert!(this_year < estimate("1900").guesses_log10);
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(estimate("").score, 0);
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(estimate("zxcvfdsa").warning, Some("Straight rows of keys are easy to guess."));
+        assert_eq!(estimate("abcdefgh").score, 0);
+        assert_eq!(estimate("aaaaaaaaaaaa").score, 0);
+        assert_eq!(estimate("1987").warning, Some("Recent years are easy to guess."));
+        assert!(estimate("drowssap").score <= 1);
+    }
+
+    #[test]
+    fn test_strong() {
+        for p in &["correct horse battery staple", "w8Qz!r2#Lp0v^Km", "tyrannosaurus-gazebo-47-lentil"] {
+            let e = estimate(p);
+            assert!(e.score >= 3, "{}: {:?}", p, e);
+        }
+    }
+
+    #[test]
+    fn test_long_repeats() {
+        for key in &["a".repeat(100), "ab".repeat(50), "abc".repeat(34), "x7!".repeat(40)] {
+            assert!(estimate(key).score <= 2);
+        }
+    }
+
+    #[test]
+    fn test_longer_is_stronger() {
+        assert!(estimate("kq3vz").guesses_log10 < estimate("kq3vzt9wmx").guesses_log10);
+    }
+}
@@ -0,0 +1,260 @@
+//! Encryption of whole directories into a single file, which is easier to move around than the
+//! directory layout of `dir`.
+//!
+//! ```text
+//! "CRYPTAR" | version (1 byte) | for the index, then every entry: length (8 bytes) | sealed data
+//! ```
+//!
+//! The index and the entries are the same as in the directory layout, with the entries stored
+//! in the order of their names, so entry `n` is the `n`th sealed data after the index.
+
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{atomic, dir, format};
+
+pub const MAGIC: &[u8] = b"CRYPTAR";
+pub const VERSION: u8 = 1;
+
+fn corrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "the archive is corrupted")
+}
+
+/// Returns whether `data` is an archive, as opposed to a single sealed file.
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn write_sealed<W: Write + ?Sized>(out: &mut W, sealed: &[u8]) -> io::Result<()> {
+    out.write_all(&(sealed.len() as u64).to_le_bytes())?;
+    out.write_all(sealed)
+}
+
+/// Encrypts the directory `input` into an archive written to `out`. The index holds the sizes and
+/// hashes of the entries and comes first, so every entry is read once to make the index, then
+/// again to be sealed and written on its own, which keeps only one entry in memory at a time.
+pub fn encrypt<W: Write + ?Sized>(
+    key: &[u8],
+    input: &Path,
+    out: &mut W,
+    options: &dir::Options,
+) -> io::Result<()> {
+    let mut entries = dir::index(input, options.follow_symlinks)?;
+
+    for e in entries.iter_mut().filter(|e| e.number.is_some()) {
+        dir::record_entry(e, &dir::entry_data(input, e)?);
+    }
+    write_header(out, &dir::seal_index(key, &entries, options.padding)?)?;
+
+    for e in entries.iter_mut().filter(|e| e.number.is_some()) {
+        atomic::check_interrupted()?;
+        let hash = e.hash;
+        let sealed = dir::seal_entry(key, e, &dir::entry_data(input, e)?, options.padding);
+
+        if e.hash != hash {
+            return Err(io::Error::other(format!(
+                "'{}' changed while it was being encrypted",
+                e.path.display()
+            )));
+        }
+        write_sealed(out, &sealed)?;
+    }
+
+    out.flush()
+}
+
+fn write_header<W: Write + ?Sized>(out: &mut W, sealed_index: &[u8]) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    write_sealed(out, sealed_index)
+}
+
+/// Writes an archive to `out` from its sealed index and the sealed data of every entry, in the
+/// order of their numbers.
+pub fn write<W: Write + ?Sized>(
+    out: &mut W,
+    sealed_index: &[u8],
+    sealed: &[&[u8]],
+) -> io::Result<()> {
+    write_header(out, sealed_index)?;
+    sealed.iter().try_for_each(|data| write_sealed(out, data))?;
+    out.flush()
+}
+
+fn check_header(data: &[u8]) -> io::Result<()> {
+    if !is_archive(data) || data.len() <= MAGIC.len() {
+        return Err(corrupted());
+    }
+
+    match data[MAGIC.len()] {
+        VERSION => Ok(()),
+        version => Err(format::FormatError::UnsupportedVersion(version).into()),
+    }
+}
+
+/// Returns the sealed index from `data`, which may be only the start of an archive, or `None` if
+/// `data` does not hold all of it yet.
+pub fn sealed_index(data: &[u8]) -> io::Result<Option<&[u8]>> {
+    let header_len = MAGIC.len() + 1 + 8;
+    if data.len() < header_len {
+        return Ok(None);
+    }
+    check_header(data)?;
+
+    let len = u64::from_le_bytes(data[MAGIC.len() + 1..header_len].try_into().unwrap());
+    match (data.len() - header_len) as u64 >= len {
+        true => Ok(Some(&data[header_len..header_len + len as usize])),
+        false => Ok(None),
+    }
+}
+
+/// Returns the sealed index from `data`, which may be only the start of an archive, failing if
+/// `data` does not hold all of it.
+pub fn index(data: &[u8]) -> io::Result<&[u8]> {
+    sealed_index(data)?.ok_or_else(corrupted)
+}
+
+/// Splits an archive into its sealed data, the first of which is the index.
+pub fn split(data: &[u8]) -> io::Result<Vec<&[u8]>> {
+    check_header(data)?;
+
+    let mut rest = &data[MAGIC.len() + 1..];
+    let mut sealed = Vec::new();
+
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Err(corrupted());
+        }
+        let (len, after) = rest.split_at(8);
+        let len = u64::from_le_bytes(len.try_into().unwrap());
+
+        if len > after.len() as u64 {
+            return Err(corrupted());
+        }
+        let (data, after) = after.split_at(len as usize);
+        sealed.push(data);
+        rest = after;
+    }
+
+    match sealed.is_empty() {
+        true => Err(corrupted()),
+        false => Ok(sealed),
+    }
+}
+
+/// Decrypts the entries of an archive into the directory `output`, given its already decrypted
+/// index `meta` and the sealed data from `split`. Only the entries matching `patterns` are, if any
+/// are given.
+pub fn decrypt(
+    key: &[u8],
+    meta: &[u8],
+    patterns: &[OsString],
+    sealed: &[&[u8]],
+    output: &Path,
+) -> io::Result<()> {
+    dir::restore(key, meta, patterns, output, |n| match sealed.get(n as usize) {
+        Some(data) if n > 0 => Ok(data.to_vec()),
+        _ => Err(corrupted()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{self, TempDir, KEY};
+    use libcrypt::padding::Padding;
+
+    fn encrypted(input: &Path, padding: Option<Padding>) -> Vec<u8> {
+        let options = dir::Options {
+            follow_symlinks: false,
+            padding,
+        };
+        let mut data = Vec::new();
+        encrypt(&KEY, input, &mut data, &options).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new("archive-round-trip");
+        let src = test::source_tree(&dir);
+
+        for (i, padding) in [None, Some(Padding::Block(1024))].iter().enumerate() {
+            let data = encrypted(&src, *padding);
+
+            // The index, two files and a symlink.
+            let sealed = split(&data).unwrap();
+            assert_eq!(sealed.len(), 4);
+            if padding.is_some() {
+                assert!(sealed.iter().all(|s| s.len() == sealed[0].len()));
+            }
+
+            assert_eq!(index(&data).unwrap(), sealed[0]);
+
+            let meta = format::open(&KEY, sealed[0]).unwrap();
+            let out = dir.join(&format!("out{}", i));
+            decrypt(&KEY, &meta, &[], &sealed, &out).unwrap();
+            assert_eq!(test::tree(&out), test::tree(&src));
+        }
+    }
+
+    #[test]
+    fn test_sealed_index() {
+        let dir = TempDir::new("archive-index");
+        let data = encrypted(&test::source_tree(&dir), None);
+        let end = MAGIC.len() + 1 + 8 + split(&data).unwrap()[0].len();
+
+        for len in 0..end {
+            assert_eq!(sealed_index(&data[..len]).unwrap(), None);
+            assert!(index(&data[..len]).is_err());
+        }
+        assert!(sealed_index(&data[..end]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let dir = TempDir::new("archive-truncated");
+        let data = encrypted(&test::source_tree(&dir), None);
+        let ends = split(&data)
+            .unwrap()
+            .iter()
+            .scan(MAGIC.len() + 1, |end, s| {
+                *end += 8 + s.len();
+                Some(*end)
+            })
+            .collect::<Vec<_>>();
+
+        // Cut anywhere but between entries, the archive is corrupted.
+        for len in 0..data.len() {
+            match ends.iter().position(|&end| end == len) {
+                Some(i) => assert_eq!(split(&data[..len]).unwrap().len(), i + 1),
+                None => assert!(split(&data[..len]).is_err()),
+            }
+        }
+
+        // Entries cut off are missing, and so corrupted.
+        let sealed = split(&data[..ends[2]]).unwrap();
+        let meta = format::open(&KEY, sealed[0]).unwrap();
+        assert!(decrypt(&KEY, &meta, &[], &sealed, &dir.join("out")).is_err());
+    }
+
+    #[test]
+    fn test_lengths() {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend_from_slice(b"sealed");
+
+        assert!(split(&data).is_err());
+        assert_eq!(sealed_index(&data).unwrap(), None);
+
+        // An archive holds at least the index.
+        assert!(split(&data[..MAGIC.len() + 1]).is_err());
+
+        data[MAGIC.len()] = VERSION + 1;
+        assert!(split(&data).is_err());
+        assert!(sealed_index(&data).is_err());
+    }
+}
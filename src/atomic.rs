@@ -0,0 +1,612 @@
+//! Writing outputs so that a crash or a full disk never leaves a partial output behind, nor
+//! destroys what was there before.
+//!
+//! Every output is first written to a temporary file or directory next to it, synced to disk and
+//! read back, and only then moved into place with a rename.
+//!
+//! Temporary files are removed if anything fails, and if crypt is interrupted by `SIGINT`,
+//! `SIGTERM`, `SIGHUP` or `SIGQUIT` while they exist, since they may hold decrypted data. Their
+//! names hold the PID of the process which made them, so the ones left behind by a crash can be
+//! told apart from the ones another crypt process is still working on:
+//!
+//! ```text
+//! .crypt.<temp|old|copy>.<PID>.<name of the output>
+//! ```
+//!
+//! Temporary files are only readable by their owner while they are written, and never opened if
+//! something is already at their path, which could be a symlink planted to make crypt write
+//! elsewhere. Once written, files get the mode of the file they replace, or the one `umask` gives
+//! new files.
+
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+const PREFIX: &str = ".crypt.";
+const KINDS: [&str; 3] = ["temp", "old", "copy"];
+
+const DEFERRED_SIGNALS: [libc::c_int; 4] =
+    [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+// How many temporary files exist, and the signal which interrupted crypt while there were any.
+// They are atomics because the signal handler reads and writes them.
+static LIVE_TEMPS: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn defer_interrupt(signal: libc::c_int) {
+    if LIVE_TEMPS.load(Ordering::SeqCst) > 0 {
+        // The temporary files cannot be removed from a signal handler, so let whatever is writing
+        // them fail, and remove them on the way out.
+        INTERRUPTED.store(signal, Ordering::SeqCst);
+        return;
+    }
+
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Makes the signals which would kill crypt wait for the temporary files to be removed first.
+/// Without temporary files, they kill crypt right away as usual.
+pub fn defer_interrupts() -> io::Result<()> {
+    for signal in DEFERRED_SIGNALS.iter() {
+        unsafe {
+            let mut action = std::mem::zeroed::<libc::sigaction>();
+            action.sa_sigaction = defer_interrupt as extern "C" fn(libc::c_int) as usize;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            if libc::sigaction(*signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Kills crypt with the signal which interrupted it, if any, now that the temporary files are
+/// removed.
+pub fn reraise_interrupt() {
+    let signal = INTERRUPTED.load(Ordering::SeqCst);
+
+    if signal != 0 {
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+/// Fails if crypt was interrupted, so that long work stops before its output is committed.
+pub fn check_interrupted() -> io::Result<()> {
+    match INTERRUPTED.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted")),
+    }
+}
+
+/// Returns `path` with its file name prefixed by the temporary file name for `kind`.
+fn sibling(path: &Path, kind: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!("{}{}.{}.{}", PREFIX, kind, process::id(), name))
+}
+
+/// Removes the file or directory at `path`, making directories writable first so that their
+/// contents can be removed.
+pub fn remove(path: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return fs::remove_file(path);
+    }
+
+    // Through a descriptor, as the sandbox refuses changing permissions by path.
+    let dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+        .open(path);
+    if let Ok(dir) = dir {
+        let _ = dir.set_permissions(fs::Permissions::from_mode(0o700));
+    }
+    for e in fs::read_dir(path)? {
+        remove(&e?.path())?;
+    }
+    fs::remove_dir(path)
+}
+
+/// A temporary file or directory which an output is built in, next to it so that moving it into
+/// place is a rename. It is removed when dropped, unless it was committed.
+pub struct Temp {
+    path: PathBuf,
+    output: PathBuf,
+    committed: bool,
+}
+
+impl Temp {
+    fn new(output: &Path) -> Self {
+        LIVE_TEMPS.fetch_add(1, Ordering::SeqCst);
+
+        let path = sibling(output, "temp");
+        // Left behind by an earlier process which had the same PID.
+        let _ = remove(&path);
+
+        Temp {
+            path,
+            output: output.to_path_buf(),
+            committed: false,
+        }
+    }
+
+    /// Creates an empty temporary directory for `output`.
+    pub fn dir(output: &Path) -> io::Result<Self> {
+        let temp = Temp::new(output);
+        fs::create_dir(&temp.path)?;
+        Ok(temp)
+    }
+
+    /// Creates an empty temporary file for `output`.
+    fn file(output: &Path) -> io::Result<(Self, File)> {
+        let temp = Temp::new(output);
+        let file = create(&temp.path)?;
+        Ok((temp, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the finished temporary file into place as the output.
+    pub fn commit(mut self) -> io::Result<()> {
+        commit(&self.path, &self.output)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Temp {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Err(e) = remove(&self.path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Warning: could not remove '{}': {}", self.path.display(), e);
+                }
+            }
+        }
+
+        LIVE_TEMPS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn parse_pid(pid: &[u8]) -> Option<libc::pid_t> {
+    match std::str::from_utf8(pid).ok()?.parse() {
+        Ok(pid) if pid > 0 => Some(pid),
+        _ => None,
+    }
+}
+
+/// Returns whether another process with the given PID is running.
+fn is_running(pid: libc::pid_t) -> bool {
+    if pid == process::id() as libc::pid_t {
+        return false;
+    }
+
+    match unsafe { libc::kill(pid, 0) } {
+        0 => true,
+        _ => io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+    }
+}
+
+/// A temporary file left behind by a crypt process which is not running anymore.
+pub struct Stale {
+    pub path: PathBuf,
+    /// The output it was for, if it is what the output was before being replaced and the output
+    /// is missing, so it is the only copy left.
+    pub replaced: Option<PathBuf>,
+}
+
+/// Returns the temporary files in `dir` left behind by crypt processes which are not running
+/// anymore.
+pub fn find_stale(dir: &Path) -> io::Result<Vec<Stale>> {
+    let mut stale = Vec::new();
+
+    for e in fs::read_dir(dir)? {
+        let e = e?;
+        let name = e.file_name();
+        let rest = match name.as_bytes().strip_prefix(PREFIX.as_bytes()) {
+            Some(rest) => rest,
+            None => continue,
+        };
+
+        let (kind, rest) = match KINDS
+            .iter()
+            .find_map(|k| Some((*k, rest.strip_prefix(k.as_bytes())?)))
+        {
+            Some((kind, [b'.', rest @ ..])) if !rest.is_empty() => (kind, rest),
+            _ => continue,
+        };
+
+        let (pid, output) = match rest.iter().position(|&b| b == b'.') {
+            Some(i) => match parse_pid(&rest[..i]) {
+                Some(pid) => (Some(pid), &rest[i + 1..]),
+                None => (None, rest),
+            },
+            // Left behind by a version of crypt which did not put PIDs in the names.
+            None => (None, rest),
+        };
+
+        if pid.is_some_and(is_running) {
+            continue;
+        }
+
+        let output = dir.join(OsStr::from_bytes(output));
+        stale.push(Stale {
+            path: e.path(),
+            replaced: match kind == "old" && fs::symlink_metadata(&output).is_err() {
+                true => Some(output),
+                false => None,
+            },
+        });
+    }
+
+    stale.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(stale)
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    }
+}
+
+/// Syncs the directory `dir`, so that the entries created or renamed in it are on disk.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Creates the new file `path`, readable only by its owner, failing if anything is already there.
+fn create(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Returns the mode of new files, which is what `umask` leaves of 0666.
+fn new_file_mode() -> u32 {
+    static UMASK: OnceLock<libc::mode_t> = OnceLock::new();
+
+    // `umask` cannot be read without setting it, so it is set back right away.
+    let umask = *UMASK.get_or_init(|| unsafe {
+        let umask = libc::umask(0o077);
+        libc::umask(umask);
+        umask
+    });
+    0o666 & !umask
+}
+
+/// Returns the mode for a file written to replace `output`, which is the mode of `output` if it
+/// is a file already.
+fn final_mode(output: &Path) -> u32 {
+    match fs::metadata(output) {
+        Ok(m) if m.is_file() => m.permissions().mode() & 0o777,
+        _ => new_file_mode(),
+    }
+}
+
+/// Writes `contents` to the new file `path` and syncs it, then reads it back to make sure it was
+/// written correctly.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with_mode(path, contents, new_file_mode())
+}
+
+fn write_with_mode(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    check_interrupted()?;
+
+    let mut file = create(path)?;
+    file.write_all(contents)?;
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    file.sync_all()?;
+
+    if fs::read(path)? != contents {
+        return Err(io::Error::other(format!(
+            "'{}' did not read back as written",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to the file `output`.
+pub fn write_file(output: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = Temp::new(output);
+
+    write_with_mode(&temp.path, contents, final_mode(output))?;
+    temp.commit()
+}
+
+/// Writes the file `output` with `write`.
+pub fn write_file_with<F>(output: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let (temp, file) = Temp::file(output)?;
+    let mut out = BufWriter::new(file);
+
+    write(&mut out)?;
+    let file = out.into_inner()?;
+    file.set_permissions(fs::Permissions::from_mode(final_mode(output)))?;
+    file.sync_all()?;
+    temp.commit()
+}
+
+/// Syncs every directory in the tree at `dir`. The files in it are synced as they are written.
+/// Directories restored without read permission are skipped.
+fn sync_tree(dir: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return Ok(()),
+        res => res?,
+    };
+
+    for e in entries {
+        let e = e?;
+        if e.file_type()?.is_dir() {
+            sync_tree(&e.path())?;
+        }
+    }
+
+    sync_dir(dir)
+}
+
+/// Copies the file or directory `from` to `to`, for when it cannot be renamed.
+fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+
+    if meta.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+    } else if meta.is_dir() {
+        fs::create_dir(to)?;
+        for e in fs::read_dir(from)? {
+            let e = e?;
+            copy(&e.path(), &to.join(e.file_name()))?;
+        }
+        // Through a descriptor, as the sandbox refuses changing permissions by path.
+        File::open(to)?.set_permissions(meta.permissions())?;
+    } else {
+        fs::copy(from, to)?;
+        File::open(to)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Moves `from` to `to` by copying it next to `to`, then renaming the copy into place and
+/// removing `from`.
+fn move_by_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let copied = sibling(to, "copy");
+    let _ = remove(&copied);
+
+    copy(from, &copied)?;
+    if fs::symlink_metadata(&copied)?.is_dir() {
+        sync_tree(&copied)?;
+    }
+    fs::rename(&copied, to)?;
+    remove(from)
+}
+
+/// Moves `from` to `to`, copying it if renaming fails with `EXDEV`.
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => move_by_copy(from, to),
+        res => res,
+    }
+}
+
+/// Moves the finished `temp` into place as `output`, which may be a file or a directory.
+///
+/// A file is replaced at once by the rename. A directory cannot be, so an existing `output` is
+/// moved out of the way first, and only removed once `temp` has taken its place. `temp` is next
+/// to `output`, yet renaming can still fail with `EXDEV`, as overlayfs does for directories from
+/// a lower layer, in which case they are copied instead.
+fn commit(temp: &Path, output: &Path) -> io::Result<()> {
+    check_interrupted()?;
+
+    if fs::symlink_metadata(temp)?.is_dir() {
+        sync_tree(temp)?;
+    }
+
+    let old = match fs::symlink_metadata(output) {
+        Ok(m) if m.is_dir() || fs::symlink_metadata(temp)?.is_dir() => {
+            let old = sibling(output, "old");
+
+            let _ = fs::remove_dir_all(&old);
+            rename(output, &old)?;
+            Some((old, m.is_dir()))
+        }
+        _ => None,
+    };
+
+    if let Err(e) = rename(temp, output) {
+        // Put back what was there before.
+        if let Some((old, _)) = &old {
+            let _ = fs::rename(old, output);
+        }
+        return Err(e);
+    }
+    sync_dir(parent(output))?;
+
+    match old {
+        Some((old, true)) => fs::remove_dir_all(old),
+        Some((old, false)) => fs::remove_file(old),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TempDir;
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = TempDir::new("atomic-write");
+        let out = dir.join("out");
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write_file(&out, b"first").unwrap();
+        assert_eq!(mode(&out), new_file_mode());
+
+        // The mode of the file which is replaced is kept.
+        fs::set_permissions(&out, fs::Permissions::from_mode(0o640)).unwrap();
+        write_file(&out, b"second").unwrap();
+        assert_eq!(fs::read(&out).unwrap(), b"second");
+        assert_eq!(mode(&out), 0o640);
+        write_file_with(&out, |w| w.write_all(b"second")).unwrap();
+        assert_eq!(mode(&out), 0o640);
+
+        // A failed write leaves what was there and nothing else.
+        let res = write_file_with(&out, |w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::other("failed"))
+        });
+        assert!(res.is_err());
+        assert_eq!(fs::read(&out).unwrap(), b"second");
+        assert_eq!(names(dir.path()), ["out"]);
+    }
+
+    #[test]
+    fn test_write_refuses_existing() {
+        let dir = TempDir::new("atomic-existing");
+        let target = dir.path().join("target");
+        let planted = dir.path().join("planted");
+        fs::write(&target, b"untouched").unwrap();
+        std::os::unix::fs::symlink(&target, &planted).unwrap();
+
+        let err = write(&planted, b"redirected").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&target).unwrap(), b"untouched");
+    }
+
+    #[test]
+    fn test_replace_dir() {
+        let dir = TempDir::new("atomic-dir");
+        let out = dir.join("out");
+        fs::create_dir(&out).unwrap();
+        fs::write(dir.path().join("out/old"), b"old").unwrap();
+
+        let temp = Temp::dir(&out).unwrap();
+        write(&temp.path().join("new"), b"new").unwrap();
+        temp.commit().unwrap();
+
+        assert_eq!(names(Path::new(&out)), ["new"]);
+        assert_eq!(names(dir.path()), ["out"]);
+
+        // Dropped without being committed.
+        let temp = Temp::dir(&out).unwrap();
+        write(&temp.path().join("other"), b"other").unwrap();
+        drop(temp);
+        assert_eq!(names(Path::new(&out)), ["new"]);
+        assert_eq!(names(dir.path()), ["out"]);
+    }
+
+    #[test]
+    fn test_move_by_copy() {
+        let dir = TempDir::new("atomic-copy");
+        let from = dir.join("from");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/file"), b"file").unwrap();
+        fs::set_permissions(from.join("sub/file"), fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink("sub/file", from.join("link")).unwrap();
+        fs::set_permissions(from.join("sub"), fs::Permissions::from_mode(0o500)).unwrap();
+
+        let to = dir.join("to");
+        let before = crate::test::tree(&from);
+        move_by_copy(&from, &to).unwrap();
+
+        assert_eq!(crate::test::tree(&to), before);
+        let mode = |path: PathBuf| fs::symlink_metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode(to.join("sub")) & 0o777, 0o500);
+        assert_eq!(mode(to.join("sub/file")) & 0o777, 0o640);
+        assert_eq!(names(dir.path()), ["to"]);
+
+        // A file replaces the one already there.
+        let file = dir.join("file");
+        fs::write(&file, b"new").unwrap();
+        move_by_copy(&file, &to.join("link")).unwrap();
+        assert_eq!(fs::read(to.join("link")).unwrap(), b"new");
+        assert_eq!(names(dir.path()), ["to"]);
+
+        fs::set_permissions(to.join("sub"), fs::Permissions::from_mode(0o700)).unwrap();
+    }
+
+    #[test]
+    fn test_remove() {
+        let dir = TempDir::new("atomic-remove");
+        let locked = dir.path().join("locked");
+        fs::create_dir_all(locked.join("sub")).unwrap();
+        fs::write(locked.join("sub/file"), b"").unwrap();
+        fs::set_permissions(locked.join("sub"), fs::Permissions::from_mode(0o500)).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o500)).unwrap();
+
+        remove(&locked).unwrap();
+        assert_eq!(names(dir.path()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_find_stale() {
+        let dir = TempDir::new("atomic-stale");
+        // No process has a PID this large, and init is always running.
+        for name in [
+            ".crypt.temp.999999999.out",
+            ".crypt.old.999999999.gone",
+            ".crypt.old.999999999.kept",
+            ".crypt.temp.1.busy",
+            ".crypt.other.999999999.out",
+            "kept",
+        ]
+        .iter()
+        {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let stale = find_stale(dir.path()).unwrap();
+        let found = stale
+            .iter()
+            .map(|s| {
+                let name = s.path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, s.replaced.clone())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            [
+                (
+                    String::from(".crypt.old.999999999.gone"),
+                    Some(dir.path().join("gone"))
+                ),
+                (String::from(".crypt.old.999999999.kept"), None),
+                (String::from(".crypt.temp.999999999.out"), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,870 @@
+//! Encryption of whole directories.
+//!
+//! The output is a directory holding the index as `00`, which lists every file, directory and
+//! symlink in the input along with its permissions, owner and modification time, and the
+//! contents of the files and the targets of the symlinks, named after their number in hex.
+//! Every one of them is sealed on its own, so any entry can be decrypted without the others. The
+//! same index and entries can also be packed into a single file, see `archive`.
+//!
+//! The index is binary, so that any path round-trips, and made of a version byte followed by every
+//! entry, with all integers in little endian:
+//!
+//! ```text
+//! kind ('f', 'd' or 'l') | number (4 bytes) | nonce (12 bytes) | size (8 bytes) |
+//! hash (32 bytes) | mode (4 bytes) | uid (4 bytes) | gid (4 bytes) | mtime (8 bytes) |
+//! mtime nanoseconds (4 bytes) | path length (4 bytes) | path
+//! ```
+//!
+//! The nonce is the one the data of the entry is sealed under, which ties the data to its entry,
+//! so that entries cannot be swapped, and the size is the length of that data, so the index alone
+//! tells what is in the directory. The hash is the SHA-256 hash of that data, which tells whether
+//! the input changed without decrypting anything. Directories have none of them.
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::ffi::{CString, OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{self as unix_fs, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+use std::{fs, io};
+
+use libcrypt::aead::NONCE_LEN;
+use libcrypt::padding::Padding;
+use libcrypt::sha256::Sha256;
+
+use crate::{atomic, format, glob};
+
+/// How a directory is encrypted.
+pub struct Options {
+    /// Encrypts what symlinks lead to, instead of the symlinks themselves.
+    pub follow_symlinks: bool,
+    /// Pads the index and every entry.
+    pub padding: Option<Padding>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// The metadata restored along with an entry.
+#[derive(PartialEq)]
+pub struct Meta {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+}
+
+impl From<&fs::Metadata> for Meta {
+    fn from(m: &fs::Metadata) -> Self {
+        Meta {
+            mode: m.mode() & 0o7777,
+            uid: m.uid(),
+            gid: m.gid(),
+            mtime: m.mtime(),
+            mtime_nsec: m.mtime_nsec(),
+        }
+    }
+}
+
+/// An entry of the index. Files and symlinks have a number, which their sealed contents or
+/// target are stored under, while directories have none.
+pub struct Entry {
+    pub number: Option<u32>,
+    /// What the data of the entry is sealed under. Missing for directories.
+    pub nonce: Option<[u8; NONCE_LEN]>,
+    pub kind: Kind,
+    /// The length of the contents of a file or the target of a symlink. Missing for directories.
+    pub size: Option<u64>,
+    /// The SHA-256 hash of the contents of a file or the target of a symlink. Missing for
+    /// directories, and for files and symlinks until they are sealed.
+    pub hash: Option<[u8; 32]>,
+    pub path: PathBuf,
+    pub meta: Meta,
+}
+
+/// Returns the entry for `path`, whose path relative to the input is `rel`, along with its
+/// metadata, or `None` if it is not a file, directory or symlink. Symlinks are stored as they are
+/// unless `follow` is set, in which case only symlinks leading to one of the `ancestors` are,
+/// since following them would never end.
+fn entry_at(
+    path: &Path,
+    rel: PathBuf,
+    follow: bool,
+    ancestors: &[(u64, u64)],
+) -> io::Result<Option<(Entry, fs::Metadata)>> {
+    let mut meta = fs::symlink_metadata(path)?;
+    if follow && meta.file_type().is_symlink() {
+        // Dangling symlinks are stored as they are.
+        if let Ok(target) = fs::metadata(path) {
+            if ancestors.contains(&(target.dev(), target.ino())) {
+                eprintln!(
+                    "Warning: not following '{}', which leads back to one of its parents.",
+                    path.display()
+                );
+            } else {
+                meta = target;
+            }
+        }
+    }
+
+    let kind = if meta.is_dir() {
+        Kind::Dir
+    } else if meta.file_type().is_symlink() {
+        Kind::Symlink
+    } else if meta.is_file() {
+        Kind::File
+    } else {
+        eprintln!(
+            "Warning: skipping '{}', which is not a file, directory or symlink.",
+            path.display()
+        );
+        return Ok(None);
+    };
+
+    let entry = Entry {
+        number: None,
+        nonce: None,
+        kind,
+        size: match kind {
+            Kind::Dir => None,
+            _ => Some(meta.len()),
+        },
+        hash: None,
+        path: rel,
+        meta: Meta::from(&meta),
+    };
+    Ok(Some((entry, meta)))
+}
+
+/// Recursively lists the contents of `dir`, whose path relative to the input is `rel`, following
+/// symlinks as `entry_at` does.
+fn get_dir_contents(
+    dir: &Path,
+    rel: &Path,
+    follow: bool,
+    ancestors: &mut Vec<(u64, u64)>,
+    entries: &mut Vec<Entry>,
+) -> io::Result<()> {
+    let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|e| e.file_name());
+
+    for e in children {
+        let path = e.path();
+        let (entry, meta) = match entry_at(&path, rel.join(e.file_name()), follow, ancestors)? {
+            Some(found) => found,
+            None => continue,
+        };
+        let rel = entry.path.clone();
+        let is_dir = entry.kind == Kind::Dir;
+        entries.push(entry);
+
+        if is_dir {
+            ancestors.push((meta.dev(), meta.ino()));
+            get_dir_contents(&path, &rel, follow, ancestors, entries)?;
+            ancestors.pop();
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives the files and symlinks among `entries` consecutive numbers from `first`, with a fresh
+/// nonce for the data of each.
+pub fn number_entries(entries: &mut [Entry], first: u32) -> io::Result<()> {
+    let files = entries.iter_mut().filter(|e| e.kind != Kind::Dir);
+
+    for (n, e) in (first..).zip(files) {
+        e.number = Some(n);
+        e.nonce = Some(format::new_nonce()?);
+    }
+
+    Ok(())
+}
+
+/// Lists everything in `input`, following symlinks if `follow` is set, with a fresh nonce for the
+/// data of every file and symlink.
+pub fn index(input: &Path, follow: bool) -> io::Result<Vec<Entry>> {
+    let root = fs::metadata(input)?;
+    let mut entries = Vec::new();
+
+    get_dir_contents(
+        input,
+        Path::new(""),
+        follow,
+        &mut vec![(root.dev(), root.ino())],
+        &mut entries,
+    )?;
+
+    number_entries(&mut entries, 1)?;
+    Ok(entries)
+}
+
+/// Lists what is at the relative path `rel` in `input`, and everything in it if it is a
+/// directory and `recursive` is set, following symlinks if `follow` is set. Nothing is numbered.
+pub fn index_path(
+    input: &Path,
+    rel: &Path,
+    follow: bool,
+    recursive: bool,
+) -> io::Result<Vec<Entry>> {
+    let mut ancestors = rel
+        .ancestors()
+        .skip(1)
+        .map(|a| fs::metadata(input.join(a)).map(|m| (m.dev(), m.ino())))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let path = input.join(rel);
+    let (entry, meta) = match entry_at(&path, rel.to_path_buf(), follow, &ancestors)? {
+        Some(found) => found,
+        None => return Ok(Vec::new()),
+    };
+    let is_dir = entry.kind == Kind::Dir;
+    let mut entries = vec![entry];
+
+    if is_dir && recursive {
+        ancestors.push((meta.dev(), meta.ino()));
+        get_dir_contents(&path, rel, follow, &mut ancestors, &mut entries)?;
+    }
+
+    Ok(entries)
+}
+
+/// Reads what is sealed for `entry`, which is the contents of a file or the target of a symlink.
+pub fn entry_data(input: &Path, entry: &Entry) -> io::Result<Vec<u8>> {
+    let path = input.join(&entry.path);
+
+    match entry.kind {
+        Kind::Symlink => Ok(fs::read_link(path)?.into_os_string().into_vec()),
+        _ => fs::read(path),
+    }
+}
+
+fn kind_byte(kind: Kind) -> u8 {
+    match kind {
+        Kind::File => b'f',
+        Kind::Dir => b'd',
+        Kind::Symlink => b'l',
+    }
+}
+
+/// Returns the name the sealed data of entry `number` is stored under.
+pub fn entry_name(number: u32) -> String {
+    format!("{:02X}", number)
+}
+
+const INDEX_VERSION: u8 = 1;
+
+/// Returns the binary index listing `entries`.
+fn index_bytes(entries: &[Entry]) -> Vec<u8> {
+    let mut meta_file = vec![INDEX_VERSION];
+
+    for e in entries {
+        let m = &e.meta;
+        let path = e.path.as_os_str().as_bytes();
+
+        meta_file.push(kind_byte(e.kind));
+        meta_file.extend_from_slice(&e.number.unwrap_or(0).to_le_bytes());
+        if e.kind != Kind::Dir {
+            meta_file.extend_from_slice(&e.nonce.expect("entries being encrypted have nonces"));
+            meta_file.extend_from_slice(&e.size.unwrap_or(0).to_le_bytes());
+            meta_file.extend_from_slice(&e.hash.unwrap_or([0; 32]));
+        }
+        meta_file.extend_from_slice(&m.mode.to_le_bytes());
+        meta_file.extend_from_slice(&m.uid.to_le_bytes());
+        meta_file.extend_from_slice(&m.gid.to_le_bytes());
+        meta_file.extend_from_slice(&m.mtime.to_le_bytes());
+        meta_file.extend_from_slice(&(m.mtime_nsec as u32).to_le_bytes());
+        meta_file.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        meta_file.extend_from_slice(path);
+    }
+
+    meta_file
+}
+
+/// Records the size and hash of `data`, which `entry` holds, in the entry.
+pub fn record_entry(entry: &mut Entry, data: &[u8]) {
+    entry.size = Some(data.len() as u64);
+    entry.hash = Some(Sha256::digest(data));
+}
+
+/// Seals `data` of `entry` under the nonce the index holds for it, recording its size and hash in
+/// the entry, so the index has to be sealed afterwards.
+pub fn seal_entry(key: &[u8], entry: &mut Entry, data: &[u8], padding: Option<Padding>) -> Vec<u8> {
+    record_entry(entry, data);
+
+    let nonce = entry.nonce.expect("entries being encrypted have nonces");
+    format::seal_with_nonce(key, &nonce, data, padding)
+}
+
+/// Opens the sealed data of `entry`, checking that it is the data the index holds for it rather
+/// than the data of another entry.
+pub fn open_entry(key: &[u8], entry: &Entry, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    if format::nonce(sealed) != entry.nonce.as_ref().map(|n| &n[..]) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the data of '{}' was swapped with the data of another entry",
+                entry.path.display()
+            ),
+        ));
+    }
+
+    Ok(format::open(key, sealed)?)
+}
+
+/// Seals the index listing `entries`, padded according to `padding`.
+pub fn seal_index(key: &[u8], entries: &[Entry], padding: Option<Padding>) -> io::Result<Vec<u8>> {
+    format::seal(key, &index_bytes(entries), padding)
+}
+
+fn corrupted() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "the index is corrupted")
+}
+
+fn parse_kind(kind: &[u8]) -> io::Result<Kind> {
+    match kind {
+        b"f" => Ok(Kind::File),
+        b"d" => Ok(Kind::Dir),
+        b"l" => Ok(Kind::Symlink),
+        _ => Err(corrupted()),
+    }
+}
+
+/// Takes the next `n` bytes off `data`.
+fn take<'a>(data: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if data.len() < n {
+        return Err(corrupted());
+    }
+    let (taken, rest) = data.split_at(n);
+    *data = rest;
+    Ok(taken)
+}
+
+fn take_u32(data: &mut &[u8]) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(take(data, 4)?.try_into().unwrap()))
+}
+
+/// Parses a decrypted index.
+pub fn parse_index(meta: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut data = match meta.split_first() {
+        Some((&INDEX_VERSION, entries)) => entries,
+        Some((&version, _)) => return Err(format::FormatError::UnsupportedVersion(version).into()),
+        None => return Err(corrupted()),
+    };
+    let mut entries = Vec::new();
+
+    while !data.is_empty() {
+        let kind = parse_kind(take(&mut data, 1)?)?;
+        let number = take_u32(&mut data)?;
+        let (number, nonce, size, hash) = match kind {
+            Kind::Dir => (None, None, None, None),
+            _ => (
+                Some(number),
+                Some(take(&mut data, NONCE_LEN)?.try_into().unwrap()),
+                Some(u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap())),
+                Some(take(&mut data, 32)?.try_into().unwrap()),
+            ),
+        };
+        let meta = Meta {
+            mode: take_u32(&mut data)?,
+            uid: take_u32(&mut data)?,
+            gid: take_u32(&mut data)?,
+            mtime: i64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap()),
+            mtime_nsec: take_u32(&mut data)? as i64,
+        };
+        let len = take_u32(&mut data)? as usize;
+        let path = PathBuf::from(OsStr::from_bytes(take(&mut data, len)?));
+
+        entries.push(Entry {
+            number,
+            nonce,
+            kind,
+            size,
+            hash,
+            path,
+            meta,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Joins the path of an entry onto `root`, refusing paths which would lead outside of it, either
+/// directly or through a symlink restored earlier.
+pub fn entry_path(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let outside = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the index has a path leading outside of the output: '{}'",
+                path.display()
+            ),
+        )
+    };
+    let mut joined = root.to_path_buf();
+
+    for (i, c) in path.components().enumerate() {
+        match c {
+            Component::Normal(c) => joined.push(c),
+            _ => return Err(outside()),
+        }
+
+        if i > 0 {
+            if let Some(parent) = joined.parent() {
+                if fs::symlink_metadata(parent).is_ok_and(|m| m.file_type().is_symlink()) {
+                    return Err(outside());
+                }
+            }
+        }
+    }
+
+    match joined == root {
+        true => Err(outside()),
+        false => Ok(joined),
+    }
+}
+
+/// Restores the owner, permissions and modification time of `path`, without following it if it is
+/// a symlink, which could have been swapped in for what was restored. The owner can only be
+/// changed by root, so failing to do so is ignored. The sandbox refuses changing the owner of a
+/// symlink, which takes its path, so symlinks keep the owner of whoever restored them.
+fn set_meta(path: &Path, kind: Kind, m: &Meta) -> io::Result<()> {
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: m.mtime,
+            tv_nsec: m.mtime_nsec,
+        },
+    ];
+
+    // Symlinks have no permissions of their own, and cannot be opened.
+    let res = match kind {
+        Kind::Symlink => {
+            let cpath = c_path(path)?;
+            unsafe {
+                libc::lchown(cpath.as_ptr(), m.uid, m.gid);
+                libc::utimensat(
+                    libc::AT_FDCWD,
+                    cpath.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            }
+        }
+        _ => {
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(path)?;
+            unsafe { libc::fchown(file.as_raw_fd(), m.uid, m.gid) };
+            file.set_permissions(fs::Permissions::from_mode(m.mode))?;
+            unsafe { libc::futimens(file.as_raw_fd(), times.as_ptr()) }
+        }
+    };
+
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Returns which of `entries` have a path, or a parent, matching one of `patterns`. Fails if a
+/// pattern matches nothing.
+pub fn matching(entries: &[Entry], patterns: &[OsString]) -> io::Result<Vec<bool>> {
+    let mut matched = vec![false; patterns.len()];
+    let matching = entries
+        .iter()
+        .map(|e| {
+            let mut matching = false;
+            for (i, pattern) in patterns.iter().enumerate() {
+                if e.path.ancestors().any(|a| glob::matches(pattern, a)) {
+                    matched[i] = true;
+                    matching = true;
+                }
+            }
+            matching
+        })
+        .collect();
+
+    match matched.iter().position(|m| !m) {
+        Some(i) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "'{}' matches nothing in the index",
+                Path::new(&patterns[i]).display()
+            ),
+        )),
+        None => Ok(matching),
+    }
+}
+
+/// Keeps the entries matching one of `patterns`, see `matching`, along with the directories
+/// leading to them.
+fn select(entries: Vec<Entry>, patterns: &[OsString]) -> io::Result<Vec<Entry>> {
+    let mut selected = matching(&entries, patterns)?;
+
+    let parents = entries
+        .iter()
+        .zip(&selected)
+        .filter(|(_, &s)| s)
+        .flat_map(|(e, _)| e.path.ancestors().skip(1))
+        .collect::<HashSet<_>>();
+    for (e, s) in entries.iter().zip(selected.iter_mut()) {
+        *s |= e.kind == Kind::Dir && parents.contains(e.path.as_path());
+    }
+
+    Ok(entries
+        .into_iter()
+        .zip(selected)
+        .filter_map(|(e, s)| if s { Some(e) } else { None })
+        .collect())
+}
+
+/// Decrypts what is listed in the decrypted index `meta` into the directory `output`, reading
+/// the sealed data of each entry from its number with `read_entry`. If `patterns` are given, only
+/// the entries matching one of them are, see `select`.
+pub fn restore<F>(
+    key: &[u8],
+    meta: &[u8],
+    patterns: &[OsString],
+    output: &Path,
+    mut read_entry: F,
+) -> io::Result<()>
+where
+    F: FnMut(u32) -> io::Result<Vec<u8>>,
+{
+    let entries = match patterns {
+        [] => parse_index(meta)?,
+        _ => select(parse_index(meta)?, patterns)?,
+    };
+    let temp = atomic::Temp::dir(output)?;
+    let temp_out = temp.path();
+
+    for e in &entries {
+        let path = entry_path(temp_out, &e.path)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match (e.kind, e.number) {
+            (Kind::Dir, _) => fs::create_dir_all(&path)?,
+            (Kind::File, Some(n)) => atomic::write(&path, &open_entry(key, e, &read_entry(n)?)?)?,
+            (Kind::Symlink, Some(n)) => {
+                let target = open_entry(key, e, &read_entry(n)?)?;
+                unix_fs::symlink(OsStr::from_bytes(&target), &path)?;
+            }
+            _ => return Err(corrupted()),
+        }
+
+        if e.kind != Kind::Dir {
+            set_meta(&path, e.kind, &e.meta)?;
+        }
+    }
+
+    // Directories get their metadata last, deepest first, since filling them in changes their
+    // modification time and they might not be writable.
+    for e in entries.iter().rev().filter(|e| e.kind == Kind::Dir) {
+        set_meta(&temp_out.join(&e.path), e.kind, &e.meta)?;
+    }
+
+    temp.commit()
+}
+
+/// Checks that the encrypted directory whose decrypted index is `meta` holds exactly what `input`
+/// does, reading the sealed data of each entry from its number with `read_entry`.
+pub fn compare<F>(
+    key: &[u8],
+    meta: &[u8],
+    input: &Path,
+    follow: bool,
+    mut read_entry: F,
+) -> io::Result<()>
+where
+    F: FnMut(u32) -> io::Result<Vec<u8>>,
+{
+    let stored = parse_index(meta)?;
+    let mut entries = index(input, follow)?;
+    let differs = |what: &Path| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the encrypted '{}' differs from the input", what.display()),
+        )
+    };
+
+    if stored.len() == entries.len() {
+        for (e, s) in entries.iter_mut().zip(&stored) {
+            e.nonce = s.nonce;
+            e.hash = s.hash;
+        }
+    }
+    if meta != &index_bytes(&entries)[..] {
+        return Err(differs(input));
+    }
+
+    for e in &entries {
+        if let Some(n) = e.number {
+            if open_entry(key, e, &read_entry(n)?)? != entry_data(input, e)? {
+                return Err(differs(&input.join(&e.path)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Encrypts the directory `input` into `output`, with the key `slot` in front of the index.
+pub fn encrypt(
+    key: &[u8],
+    slot: &[u8],
+    input: &Path,
+    output: &Path,
+    options: &Options,
+) -> io::Result<()> {
+    let mut entries = index(input, options.follow_symlinks)?;
+    let temp = atomic::Temp::dir(output)?;
+    let temp_out = temp.path();
+
+    for e in entries.iter_mut() {
+        if let Some(n) = e.number {
+            let data = entry_data(input, e)?;
+            atomic::write(
+                &temp_out.join(entry_name(n)),
+                &seal_entry(key, e, &data, options.padding),
+            )?;
+        }
+    }
+
+    // Sealed last, since it holds the hashes of the entries.
+    atomic::write(
+        &temp_out.join("00"),
+        &[slot, &seal_index(key, &entries, options.padding)?].concat(),
+    )?;
+
+    temp.commit()
+}
+
+/// Decrypts the directory `input` into `output`, given its already decrypted index `meta`, or only
+/// the entries matching `patterns` if any are given.
+pub fn decrypt(
+    key: &[u8],
+    meta: &[u8],
+    patterns: &[OsString],
+    input: &Path,
+    output: &Path,
+) -> io::Result<()> {
+    restore(key, meta, patterns, output, |n| {
+        fs::read(input.join(entry_name(n)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{self, TempDir, KEY};
+
+    const OPTIONS: Options = Options {
+        follow_symlinks: false,
+        padding: None,
+    };
+
+    /// Encrypts `input` into `output`, returning its decrypted index.
+    fn encrypted(input: &Path, output: &Path) -> Vec<u8> {
+        encrypt(&KEY, b"", input, output, &OPTIONS).unwrap();
+        format::open(&KEY, &fs::read(output.join("00")).unwrap()).unwrap()
+    }
+
+    fn file(path: &str, number: u32) -> Entry {
+        Entry {
+            number: Some(number),
+            nonce: Some([number as u8; NONCE_LEN]),
+            kind: Kind::File,
+            size: Some(5),
+            hash: Some([1; 32]),
+            path: PathBuf::from(path),
+            meta: Meta {
+                mode: 0o644,
+                uid: 1,
+                gid: 2,
+                mtime: 3,
+                mtime_nsec: 4,
+            },
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new("dir-round-trip");
+        let src = test::source_tree(&dir);
+        fs::set_permissions(src.join("a"), fs::Permissions::from_mode(0o640)).unwrap();
+
+        let meta = encrypted(&src, &dir.join("enc"));
+        let out = dir.join("out");
+        decrypt(&KEY, &meta, &[], &dir.join("enc"), &out).unwrap();
+
+        assert_eq!(test::tree(&out), test::tree(&src));
+        for path in ["a", "sub", "sub/b", "sub/empty", "l"].iter() {
+            let (src, out) = (Path::new(&src).join(path), Path::new(&out).join(path));
+            let (src, out) = (fs::symlink_metadata(src), fs::symlink_metadata(out));
+            assert!(
+                Meta::from(&src.unwrap()) == Meta::from(&out.unwrap()),
+                "{}",
+                path
+            );
+        }
+        compare(&KEY, &meta, &src, false, |n| {
+            fs::read(Path::new(&dir.join("enc")).join(entry_name(n)))
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_swapped() {
+        let dir = TempDir::new("dir-swapped");
+        let src = test::source_tree(&dir);
+        let enc = dir.join("enc");
+        let meta = encrypted(&src, &enc);
+
+        let (first, second) = (enc.join("01"), enc.join("02"));
+        let data = fs::read(&first).unwrap();
+        fs::copy(&second, &first).unwrap();
+        fs::write(&second, data).unwrap();
+
+        let err = decrypt(&KEY, &meta, &[], &enc, &dir.join("out")).unwrap_err();
+        assert!(err.to_string().contains("swapped"), "{}", err);
+        assert!(fs::symlink_metadata(dir.join("out")).is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let dir = TempDir::new("dir-select");
+        let src = test::source_tree(&dir);
+        let selected = |patterns: &[&str]| {
+            let patterns = patterns.iter().map(OsString::from).collect::<Vec<_>>();
+            let mut paths = select(index(&src, false).unwrap(), &patterns)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(selected(&["sub/b"]), ["sub", "sub/b"]);
+        assert_eq!(selected(&["sub"]), ["sub", "sub/b", "sub/empty"]);
+        assert_eq!(selected(&["a", "l"]), ["a", "l"]);
+        assert_eq!(selected(&["*"]).len(), 5);
+
+        let patterns = [OsString::from("a"), OsString::from("nothing")];
+        let res = select(index(&src, false).unwrap(), &patterns);
+        assert!(matches!(res, Err(e) if e.kind() == io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let mut d = file("d", 0);
+        d.kind = Kind::Dir;
+        d.number = None;
+        d.nonce = None;
+        d.size = None;
+        d.hash = None;
+        let not_utf8 = PathBuf::from(OsStr::from_bytes(b"d/\xff\n"));
+        let mut entries = vec![d, file("d/\u{e9}\n", 1), file("l", 2)];
+        entries.push(Entry {
+            path: not_utf8.clone(),
+            ..file("", 3)
+        });
+
+        let bytes = index_bytes(&entries);
+        let parsed = parse_index(&bytes).unwrap();
+        assert_eq!(parsed.len(), entries.len());
+        for (e, p) in entries.iter().zip(&parsed) {
+            assert_eq!(
+                (e.number, e.nonce, e.size, e.hash, &e.path),
+                (p.number, p.nonce, p.size, p.hash, &p.path)
+            );
+            assert!(e.kind == p.kind && e.meta == p.meta);
+        }
+        assert_eq!(index_bytes(&parsed), bytes);
+        assert_eq!(parsed[3].path, not_utf8);
+    }
+
+    #[test]
+    fn test_corrupted_index() {
+        let bytes = index_bytes(&[file("a", 1), file("b", 2)]);
+        let second = bytes.len() - (bytes.len() - 1) / 2;
+
+        // Cut anywhere but between entries, the index is corrupted.
+        for len in 0..bytes.len() {
+            match len {
+                1 => assert_eq!(parse_index(&bytes[..len]).unwrap().len(), 0),
+                _ if len == second => assert_eq!(parse_index(&bytes[..len]).unwrap().len(), 1),
+                _ => assert!(parse_index(&bytes[..len]).is_err(), "{}", len),
+            }
+        }
+
+        let mut bytes = bytes;
+        bytes[1] = b'x';
+        assert!(parse_index(&bytes).is_err());
+        bytes[0] = INDEX_VERSION + 1;
+        assert!(parse_index(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_entry_path() {
+        let dir = TempDir::new("dir-entry-path");
+        let root = dir.path();
+
+        assert_eq!(
+            entry_path(root, Path::new("a/b")).unwrap(),
+            root.join("a/b")
+        );
+        for path in ["", ".", "..", "../a", "a/../../b", "/etc/passwd"].iter() {
+            assert!(entry_path(root, Path::new(path)).is_err(), "{}", path);
+        }
+
+        // Through a symlink restored earlier.
+        unix_fs::symlink("/tmp", root.join("l")).unwrap();
+        assert!(entry_path(root, Path::new("l")).is_ok());
+        assert!(entry_path(root, Path::new("l/a")).is_err());
+    }
+
+    #[test]
+    fn test_restore_outside() {
+        let dir = TempDir::new("dir-outside");
+        let outside = dir.join("outside");
+        fs::create_dir(&outside).unwrap();
+
+        // A symlink leading outside of the output, and a file restored through it.
+        let mut link = file("l", 1);
+        link.kind = Kind::Symlink;
+        let mut escaped = file("l/escaped", 2);
+        let sealed = [
+            seal_entry(&KEY, &mut link, outside.as_os_str().as_bytes(), None),
+            seal_entry(&KEY, &mut escaped, b"escaped", None),
+        ];
+        let meta = index_bytes(&[link, escaped]);
+
+        let out = dir.join("out");
+        let res = restore(&KEY, &meta, &[], &out, |n| {
+            Ok(sealed[n as usize - 1].clone())
+        });
+        assert!(res.is_err());
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+        assert!(fs::symlink_metadata(out).is_err());
+    }
+}
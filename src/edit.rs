@@ -0,0 +1,535 @@
+//! Changes to encrypted directories which only seal the entries being added or updated, along
+//! with a new index, and leave the sealed data of every other entry as it is.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::{fmt, fs, io};
+
+use libcrypt::padding::Padding;
+use libcrypt::sha256::Sha256;
+
+use crate::dir::{self, Entry, Kind};
+use crate::{archive, atomic, format};
+
+/// The entries of an encrypted directory once changed.
+pub struct Edit {
+    /// What the new index lists.
+    pub entries: Vec<Entry>,
+    /// The sealed data of the entries which were added or updated, by number.
+    pub added: Vec<(u32, Vec<u8>)>,
+    /// The numbers of the entries which were removed or updated.
+    pub removed: Vec<u32>,
+}
+
+/// Takes the entries at `path` and in it out of `entries`, adding their numbers to `removed`.
+fn take(entries: &mut Vec<Entry>, path: &Path, removed: &mut Vec<u32>) {
+    entries.retain(|e| match e.path.starts_with(path) {
+        true => {
+            removed.extend(e.number);
+            false
+        }
+        false => true,
+    });
+}
+
+/// Removes the entries matching one of `patterns`, see `dir::matching`, and everything in them.
+pub fn remove(entries: Vec<Entry>, patterns: &[OsString]) -> io::Result<Edit> {
+    let matching = dir::matching(&entries, patterns)?;
+    let mut kept = Vec::new();
+    let mut removed = Vec::new();
+
+    for (e, matching) in entries.into_iter().zip(matching) {
+        match matching {
+            true => removed.extend(e.number),
+            false => kept.push(e),
+        }
+    }
+
+    Ok(Edit {
+        entries: kept,
+        added: Vec::new(),
+        removed,
+    })
+}
+
+/// Turns `path` into a path relative to the source, refusing anything leading out of it.
+fn relative(path: &Path) -> io::Result<PathBuf> {
+    let mut rel = PathBuf::new();
+
+    for c in path.components() {
+        match c {
+            Component::Normal(c) => rel.push(c),
+            Component::CurDir => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "'{}' is not a path inside of the source directory",
+                        path.display()
+                    ),
+                ))
+            }
+        }
+    }
+
+    match rel.as_os_str().is_empty() {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' is the source directory itself, encrypt it instead",
+                path.display()
+            ),
+        )),
+        false => Ok(rel),
+    }
+}
+
+/// Seals what is at `paths` in the directory `source` into the encrypted directory holding
+/// `entries`, along with everything in them and any parent missing from the index. Paths which
+/// are already listed are refused, unless `update` is set, in which case only those are accepted
+/// and their entries are replaced, or removed if they are no longer in `source`.
+pub fn add(
+    key: &[u8],
+    mut entries: Vec<Entry>,
+    source: &Path,
+    paths: &[OsString],
+    update: bool,
+    options: &dir::Options,
+) -> io::Result<Edit> {
+    let mut next = entries.iter().filter_map(|e| e.number).max().unwrap_or(0) + 1;
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+
+    for path in paths.iter().map(Path::new) {
+        let rel = relative(path)?;
+        let listed = entries.iter().any(|e| e.path == rel);
+
+        match (listed, update) {
+            (true, false) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "'{}' is already encrypted, use 'update' to replace it",
+                        path.display()
+                    ),
+                ))
+            }
+            (false, true) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "'{}' is not encrypted yet, use 'add' to add it",
+                        path.display()
+                    ),
+                ))
+            }
+            _ => {}
+        }
+
+        take(&mut entries, &rel, &mut removed);
+        if update && fs::symlink_metadata(source.join(&rel)).is_err() {
+            continue;
+        }
+
+        // Parents come first, from the top.
+        let mut new = Vec::new();
+        let mut parents = rel.ancestors().skip(1).collect::<Vec<_>>();
+        parents.pop();
+        let follow = options.follow_symlinks;
+        for parent in parents.into_iter().rev() {
+            if !entries.iter().any(|e| e.path == parent) {
+                new.extend(dir::index_path(source, parent, follow, false)?);
+            }
+        }
+        new.extend(dir::index_path(source, &rel, follow, true)?);
+
+        dir::number_entries(&mut new, next)?;
+        for e in new.iter_mut() {
+            if let Some(n) = e.number {
+                let data = dir::entry_data(source, e)?;
+                added.push((n, dir::seal_entry(key, e, &data, options.padding)));
+                next = n + 1;
+            }
+        }
+        entries.extend(new);
+    }
+
+    // Listed in the same order as when encrypting, so parents come before what they hold.
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(Edit {
+        entries,
+        added,
+        removed,
+    })
+}
+
+/// How many files and symlinks `sync` sealed, removed or kept.
+#[derive(Default)]
+pub struct Summary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed, {} unchanged",
+            self.added, self.updated, self.removed, self.unchanged
+        )
+    }
+}
+
+/// Keeps the sealed data `old` holds for `e`.
+fn keep(e: &mut Entry, old: &Entry) {
+    e.number = old.number;
+    e.nonce = old.nonce;
+    e.size = old.size;
+    e.hash = old.hash;
+}
+
+/// Brings the encrypted directory holding `entries` in line with the directory `source`. Files
+/// and symlinks with the size and modification time the index holds for them are taken to be
+/// unchanged, and so are the ones whose data still has the hash it holds, so only what changed
+/// is sealed again. Returns `None` as the edit if nothing changed at all.
+pub fn sync(
+    key: &[u8],
+    entries: Vec<Entry>,
+    source: &Path,
+    options: &dir::Options,
+) -> io::Result<(Option<Edit>, Summary)> {
+    let mut next = entries.iter().filter_map(|e| e.number).max().unwrap_or(0) + 1;
+    let mut stored = entries
+        .into_iter()
+        .map(|e| (e.path.clone(), e))
+        .collect::<HashMap<_, _>>();
+    let mut summary = Summary::default();
+    let mut changed = false;
+    let mut edit = Edit {
+        entries: Vec::new(),
+        added: Vec::new(),
+        removed: Vec::new(),
+    };
+
+    for mut e in dir::index(source, options.follow_symlinks)? {
+        let old = stored.remove(&e.path);
+        changed |= old.as_ref().is_none_or(|old| old.meta != e.meta);
+
+        // What was replaced by something of another kind counts as removed, and what replaced it
+        // as added.
+        let old = match old {
+            Some(old) if old.kind != e.kind => {
+                if let Some(n) = old.number {
+                    edit.removed.push(n);
+                    summary.removed += 1;
+                }
+                None
+            }
+            old => old,
+        };
+
+        if e.kind == Kind::Dir {
+            edit.entries.push(e);
+            continue;
+        }
+        let mtime = |e: &Entry| (e.meta.mtime, e.meta.mtime_nsec);
+
+        match &old {
+            Some(old) if old.size == e.size && mtime(old) == mtime(&e) => {
+                keep(&mut e, old);
+                summary.unchanged += 1;
+            }
+            _ => {
+                let data = dir::entry_data(source, &e)?;
+
+                match &old {
+                    Some(old) if old.hash == Some(Sha256::digest(&data)) => {
+                        keep(&mut e, old);
+                        summary.unchanged += 1;
+                    }
+                    _ => {
+                        match &old {
+                            Some(old) => {
+                                edit.removed.extend(old.number);
+                                summary.updated += 1;
+                            }
+                            None => summary.added += 1,
+                        }
+
+                        e.number = Some(next);
+                        e.nonce = Some(format::new_nonce()?);
+                        let sealed = dir::seal_entry(key, &mut e, &data, options.padding);
+                        edit.added.push((next, sealed));
+                        next += 1;
+                    }
+                }
+            }
+        }
+
+        edit.entries.push(e);
+    }
+
+    // Whatever is left is no longer in the source.
+    for old in stored.values() {
+        if let Some(n) = old.number {
+            edit.removed.push(n);
+            summary.removed += 1;
+        }
+        changed = true;
+    }
+
+    match changed || !edit.added.is_empty() {
+        true => Ok((Some(edit), summary)),
+        false => Ok((None, summary)),
+    }
+}
+
+/// Stores `edit` in the encrypted directory `output`. The sealed data of new entries is written
+/// before the index listing it, and the data no longer listed is removed last, so that the index
+/// always lists data the directory holds. The key `slot` is written in front of the index.
+pub fn write_dir(
+    key: &[u8],
+    slot: &[u8],
+    edit: &Edit,
+    padding: Option<Padding>,
+    output: &Path,
+) -> io::Result<()> {
+    let path = |name: &str| output.join(name);
+
+    for (n, sealed) in &edit.added {
+        atomic::write_file(&path(&dir::entry_name(*n)), sealed)?;
+    }
+    let index = dir::seal_index(key, &edit.entries, padding)?;
+    atomic::write_file(&path("00"), &[slot, &index].concat())?;
+
+    for n in &edit.removed {
+        match fs::remove_file(path(&dir::entry_name(*n))) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `edit` to the archive `output`, whose sealed data from `archive::split` is `sealed`.
+/// Entries are numbered again, since an archive stores them in the order of their numbers. The
+/// key `slot` is written in front of the archive.
+pub fn write_archive(
+    key: &[u8],
+    slot: &[u8],
+    mut edit: Edit,
+    sealed: &[&[u8]],
+    padding: Option<Padding>,
+    output: &Path,
+) -> io::Result<()> {
+    let added = edit
+        .added
+        .iter()
+        .map(|(n, data)| (*n, &data[..]))
+        .collect::<HashMap<_, _>>();
+    let mut data = Vec::new();
+
+    for e in edit.entries.iter_mut() {
+        if let Some(n) = e.number {
+            let sealed = match added.get(&n) {
+                Some(sealed) => *sealed,
+                None => *sealed.get(n as usize).filter(|_| n > 0).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "the archive is corrupted")
+                })?,
+            };
+            data.push(sealed);
+            e.number = Some(data.len() as u32);
+        }
+    }
+
+    let index = dir::seal_index(key, &edit.entries, padding)?;
+    atomic::write_file_with(output, |out| {
+        out.write_all(slot)?;
+        archive::write(out, &index, &data)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{self, TempDir, KEY};
+
+    const OPTIONS: dir::Options = dir::Options {
+        follow_symlinks: false,
+        padding: None,
+    };
+
+    /// The entries of the encrypted directory `enc`.
+    fn entries(enc: &Path) -> Vec<Entry> {
+        let index = format::open(&KEY, &fs::read(enc.join("00")).unwrap()).unwrap();
+        dir::parse_index(&index).unwrap()
+    }
+
+    fn decrypted(enc: &Path, out: &Path) -> Vec<(PathBuf, String)> {
+        let index = format::open(&KEY, &fs::read(enc.join("00")).unwrap()).unwrap();
+        dir::decrypt(&KEY, &index, &[], enc, out).unwrap();
+        test::tree(out)
+    }
+
+    fn paths(paths: &[&str]) -> Vec<OsString> {
+        paths.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_add() {
+        let dir = TempDir::new("edit-add");
+        let src = test::source_tree(&dir);
+        let enc = dir.join("enc");
+        dir::encrypt(&KEY, b"", &src, &enc, &OPTIONS).unwrap();
+
+        fs::create_dir_all(src.join("new")).unwrap();
+        fs::write(src.join("new/d"), b"delta").unwrap();
+        let edit = add(
+            &KEY,
+            entries(&enc),
+            &src,
+            &paths(&["new/d"]),
+            false,
+            &OPTIONS,
+        )
+        .unwrap();
+
+        // The missing parent is added along with the file, which is numbered after the others.
+        assert_eq!(edit.added.iter().map(|a| a.0).collect::<Vec<_>>(), [4]);
+        assert!(edit.removed.is_empty());
+        assert!(edit.entries.iter().any(|e| e.path == Path::new("new")));
+
+        write_dir(&KEY, b"", &edit, None, &enc).unwrap();
+        assert_eq!(decrypted(&enc, &dir.join("out")), test::tree(&src));
+    }
+
+    #[test]
+    fn test_add_refused() {
+        let dir = TempDir::new("edit-add-refused");
+        let src = test::source_tree(&dir);
+        let enc = dir.join("enc");
+        dir::encrypt(&KEY, b"", &src, &enc, &OPTIONS).unwrap();
+        fs::write(src.join("c"), b"gamma").unwrap();
+
+        let refused = |path: &str, update: bool| {
+            add(&KEY, entries(&enc), &src, &paths(&[path]), update, &OPTIONS).is_err()
+        };
+        assert!(refused("a", false));
+        assert!(refused("sub/b", false));
+        assert!(refused("c", true));
+        for path in ["../a", "/a", "sub/../a", ".", "sub/.."].iter() {
+            assert!(refused(path, false), "{}", path);
+        }
+        assert!(!refused("./c", false));
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let dir = TempDir::new("edit-update");
+        let src = test::source_tree(&dir);
+        let enc = dir.join("enc");
+        dir::encrypt(&KEY, b"", &src, &enc, &OPTIONS).unwrap();
+
+        fs::write(src.join("a"), b"alpha, again").unwrap();
+        fs::remove_file(src.join("sub/b")).unwrap();
+        let edit = add(
+            &KEY,
+            entries(&enc),
+            &src,
+            &paths(&["a", "sub/b"]),
+            true,
+            &OPTIONS,
+        );
+        let edit = edit.unwrap();
+
+        assert_eq!(edit.added.iter().map(|a| a.0).collect::<Vec<_>>(), [4]);
+        assert_eq!(edit.removed, [1, 3]);
+        write_dir(&KEY, b"", &edit, None, &enc).unwrap();
+        assert_eq!(decrypted(&enc, &dir.join("out")), test::tree(&src));
+        assert!(!Path::new(&enc.join("01")).exists());
+
+        let edit = remove(entries(&enc), &paths(&["sub"])).unwrap();
+        assert!(edit.entries.iter().all(|e| !e.path.starts_with("sub")));
+        write_dir(&KEY, b"", &edit, None, &enc).unwrap();
+        fs::remove_dir_all(src.join("sub")).unwrap();
+        assert_eq!(decrypted(&enc, &dir.join("out2")), test::tree(&src));
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = TempDir::new("edit-sync");
+        let src = test::source_tree(&dir);
+        let enc = dir.join("enc");
+        dir::encrypt(&KEY, b"", &src, &enc, &OPTIONS).unwrap();
+
+        let (edit, summary) = sync(&KEY, entries(&enc), &src, &OPTIONS).unwrap();
+        assert!(edit.is_none());
+        assert_eq!(
+            summary.to_string(),
+            "0 added, 0 updated, 0 removed, 3 unchanged"
+        );
+
+        fs::write(src.join("a"), b"alpha, again").unwrap();
+        fs::remove_file(src.join("sub/b")).unwrap();
+        fs::write(src.join("c"), b"gamma").unwrap();
+        let (edit, summary) = sync(&KEY, entries(&enc), &src, &OPTIONS).unwrap();
+        assert_eq!(
+            summary.to_string(),
+            "1 added, 1 updated, 1 removed, 1 unchanged"
+        );
+
+        write_dir(&KEY, b"", &edit.unwrap(), None, &enc).unwrap();
+        assert_eq!(decrypted(&enc, &dir.join("out")), test::tree(&src));
+        assert!(sync(&KEY, entries(&enc), &src, &OPTIONS)
+            .unwrap()
+            .0
+            .is_none());
+    }
+
+    #[test]
+    fn test_renumbered() {
+        let dir = TempDir::new("edit-renumbered");
+        let src = test::source_tree(&dir);
+        let mut data = Vec::new();
+        archive::encrypt(&KEY, &src, &mut data, &OPTIONS).unwrap();
+        let sealed = archive::split(&data).unwrap();
+        let index = dir::parse_index(&format::open(&KEY, sealed[0]).unwrap()).unwrap();
+
+        // Removing the symlink leaves a gap between the two files.
+        let edit = remove(index, &paths(&["l"])).unwrap();
+        let output = dir.join("out.crypt");
+        write_archive(&KEY, b"", edit, &sealed, None, &output).unwrap();
+
+        let data = fs::read(&output).unwrap();
+        let sealed = archive::split(&data).unwrap();
+        let meta = format::open(&KEY, sealed[0]).unwrap();
+        let numbers = dir::parse_index(&meta).unwrap();
+        let numbers = numbers.iter().filter_map(|e| e.number).collect::<Vec<_>>();
+        assert_eq!(numbers, [1, 2]);
+
+        archive::decrypt(&KEY, &meta, &[], &sealed, &dir.join("out")).unwrap();
+        fs::remove_file(src.join("l")).unwrap();
+        assert_eq!(test::tree(&dir.join("out")), test::tree(&src));
+    }
+
+    #[test]
+    fn test_renumbered_corrupted() {
+        let dir = TempDir::new("edit-renumbered-corrupted");
+        let src = test::source_tree(&dir);
+        let mut data = Vec::new();
+        archive::encrypt(&KEY, &src, &mut data, &OPTIONS).unwrap();
+        let sealed = archive::split(&data).unwrap();
+        let index = dir::parse_index(&format::open(&KEY, sealed[0]).unwrap()).unwrap();
+
+        // An index listing more entries than the archive holds.
+        let edit = remove(index, &[]).unwrap();
+        let output = dir.join("out.crypt");
+        assert!(write_archive(&KEY, b"", edit, &sealed[..2], None, &output).is_err());
+        assert!(!Path::new(&output).exists());
+    }
+}
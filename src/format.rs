@@ -0,0 +1,293 @@
+//! The format of everything crypt writes: a short header identifying the data as encrypted by
+//! crypt, followed by the data sealed with ChaCha20-Poly1305.
+//!
+//! ```text
+//! "CRYPT" | version (1 byte) | kind (1 byte) | nonce (12 bytes) | ciphertext | tag (16 bytes)
+//! ```
+//!
+//! The header is authenticated along with the ciphertext, so a wrong key or any modification is
+//! detected before anything is decrypted. The plaintext is always padded, see `padding`, with a
+//! single byte when its length is not to be hidden. The kind tells what the plaintext is: data,
+//! a single file sealed along with its original name, or a blob of a packed directory.
+//!
+//! ```text
+//! name length (2 bytes) | name | contents
+//! ```
+//!
+//! Everything is sealed with a random key, which is kept sealed with the password in a key slot
+//! written in front of it, see `keyslot`.
+
+use std::{fmt, io};
+
+use libcrypt::aead::{self, NONCE_LEN, TAG_LEN};
+use libcrypt::padding::{self, Padding};
+use libcrypt::random;
+
+pub const MAGIC: &[u8] = b"CRYPT";
+pub const VERSION: u8 = 1;
+/// The index or an entry of a directory, or the key in a key slot.
+pub const KIND_DATA: u8 = 0;
+/// A single file along with its original name.
+pub const KIND_FILE: u8 = 1;
+/// A blob of a packed directory.
+pub const KIND_BLOB: u8 = 2;
+pub const HEADER_LEN: usize = MAGIC.len() + 2 + NONCE_LEN;
+
+/// The reasons sealed data cannot be opened.
+#[derive(Debug)]
+pub enum FormatError {
+    /// The data was written by a newer version of crypt.
+    UnsupportedVersion(u8),
+    /// The data does not match its tag, because the key is wrong or the data was modified.
+    Authentication,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported format version {}, try a newer version of crypt",
+                    v
+                )
+            }
+            FormatError::Authentication => write!(f, "wrong key, or the data is corrupted"),
+        }
+    }
+}
+
+impl From<FormatError> for io::Error {
+    fn from(e: FormatError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+/// Returns whether `data` starts with the crypt header and is long enough to hold a tag.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN + TAG_LEN && data.starts_with(MAGIC)
+}
+
+/// Returns the kind of what `data` seals.
+pub fn kind(data: &[u8]) -> Option<u8> {
+    match is_sealed(data) {
+        true => Some(data[MAGIC.len() + 1]),
+        false => None,
+    }
+}
+
+/// Returns the nonce `data` is sealed under.
+pub fn nonce(data: &[u8]) -> Option<&[u8]> {
+    match is_sealed(data) {
+        true => Some(&data[MAGIC.len() + 2..HEADER_LEN]),
+        false => None,
+    }
+}
+
+/// Returns a fresh random nonce.
+pub fn new_nonce() -> io::Result<[u8; NONCE_LEN]> {
+    let mut nonce = [0; NONCE_LEN];
+    random::fill(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Encrypts `plaintext` under a fresh random nonce, padding it first if `padding` is given.
+pub fn seal(key: &[u8], plaintext: &[u8], padding: Option<Padding>) -> io::Result<Vec<u8>> {
+    Ok(seal_with_nonce(key, &new_nonce()?, plaintext, padding))
+}
+
+/// Encrypts `plaintext` like `seal`, under `nonce`, which must never be used twice with the same
+/// key.
+pub fn seal_with_nonce(
+    key: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    plaintext: &[u8],
+    padding: Option<Padding>,
+) -> Vec<u8> {
+    seal_as(key, nonce, KIND_DATA, plaintext, padding)
+}
+
+/// Encrypts a blob of a packed directory, padded to `size`.
+pub fn seal_blob(key: &[u8], plaintext: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    Ok(seal_as(
+        key,
+        &new_nonce()?,
+        KIND_BLOB,
+        plaintext,
+        Some(Padding::Block(size)),
+    ))
+}
+
+/// Encrypts the contents of a file along with its original `name`, which is empty for stdin,
+/// padding both if `padding` is given.
+pub fn seal_named(
+    key: &[u8],
+    name: &[u8],
+    contents: &[u8],
+    padding: Option<Padding>,
+) -> io::Result<Vec<u8>> {
+    if name.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the file name is too long",
+        ));
+    }
+
+    let mut plaintext = Vec::with_capacity(2 + name.len() + contents.len());
+    plaintext.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    plaintext.extend_from_slice(name);
+    plaintext.extend_from_slice(contents);
+
+    Ok(seal_as(key, &new_nonce()?, KIND_FILE, &plaintext, padding))
+}
+
+fn seal_as(
+    key: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    kind: u8,
+    plaintext: &[u8],
+    padding: Option<Padding>,
+) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 1 + TAG_LEN);
+    sealed.extend_from_slice(MAGIC);
+    sealed.push(VERSION);
+    sealed.push(kind);
+    sealed.extend_from_slice(nonce);
+
+    let mut padded = plaintext.to_vec();
+    padding::pad(&mut padded, padding.unwrap_or(Padding::Block(1)));
+    let ciphertext = aead::seal(key, nonce, &sealed, &padded);
+    sealed.extend_from_slice(&ciphertext);
+
+    sealed
+}
+
+/// Authenticates and decrypts data written by `seal`, `seal_named` or `seal_blob`.
+pub fn open(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>, FormatError> {
+    if !is_sealed(sealed) {
+        return Err(FormatError::Authentication);
+    }
+
+    let version = sealed[MAGIC.len()];
+    if version != VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    let mut nonce = [0; NONCE_LEN];
+    nonce.copy_from_slice(&header[MAGIC.len() + 2..]);
+
+    let plaintext =
+        aead::open(key, &nonce, header, ciphertext).ok_or(FormatError::Authentication)?;
+
+    padding::unpad(&plaintext)
+        .map(|p| p.to_vec())
+        .ok_or(FormatError::Authentication)
+}
+
+/// Returns whether the `plaintext` opened from `sealed` was padded by more than the single byte
+/// everything is. Padding which happened to add nothing more is not told apart.
+pub fn is_padded(sealed: &[u8], plaintext: &[u8]) -> bool {
+    sealed.len() > HEADER_LEN + plaintext.len() + 1 + TAG_LEN
+}
+
+/// Splits the opened `plaintext` of `sealed`, a single file sealed by `seal_named`, into its
+/// original name, unless it was read from stdin, and its contents.
+pub fn split_name<'a>(
+    sealed: &[u8],
+    plaintext: &'a [u8],
+) -> Result<(Option<&'a [u8]>, &'a [u8]), FormatError> {
+    if kind(sealed) != Some(KIND_FILE) || plaintext.len() < 2 {
+        return Err(FormatError::Authentication);
+    }
+    let len = u16::from_le_bytes([plaintext[0], plaintext[1]]) as usize;
+
+    match plaintext[2..].len() >= len {
+        true => Ok((
+            Some(&plaintext[2..2 + len]).filter(|name| !name.is_empty()),
+            &plaintext[2 + len..],
+        )),
+        false => Err(FormatError::Authentication),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn test_round_trip() {
+        for padding in [None, Some(Padding::Padme), Some(Padding::Block(64))].iter() {
+            for len in [0, 1, 63, 64, 1000].iter() {
+                let plaintext = vec![0x80; *len];
+                let sealed = seal(&KEY, &plaintext, *padding).unwrap();
+
+                assert_eq!(kind(&sealed), Some(KIND_DATA));
+                assert_eq!(open(&KEY, &sealed).unwrap(), plaintext);
+                if padding.is_none() {
+                    assert!(!is_padded(&sealed, &plaintext));
+                }
+            }
+        }
+
+        let sealed = seal(&KEY, b"short", Some(Padding::Block(64))).unwrap();
+        assert!(is_padded(&sealed, b"short"));
+
+        let sealed = seal_blob(&KEY, b"blob", 100).unwrap();
+        assert_eq!(kind(&sealed), Some(KIND_BLOB));
+        assert_eq!(sealed.len(), HEADER_LEN + 100 + TAG_LEN);
+        assert_eq!(open(&KEY, &sealed).unwrap(), b"blob");
+    }
+
+    #[test]
+    fn test_named() {
+        let sealed = seal_named(&KEY, b"name.txt", b"contents", None).unwrap();
+        let plaintext = open(&KEY, &sealed).unwrap();
+        assert_eq!(
+            split_name(&sealed, &plaintext).unwrap(),
+            (Some(&b"name.txt"[..]), &b"contents"[..])
+        );
+
+        // Read from stdin.
+        let sealed = seal_named(&KEY, b"", b"contents", None).unwrap();
+        let plaintext = open(&KEY, &sealed).unwrap();
+        assert_eq!(
+            split_name(&sealed, &plaintext).unwrap(),
+            (None, &b"contents"[..])
+        );
+
+        // Only single files have names.
+        let sealed = seal(&KEY, b"\x08\x00name.txt", None).unwrap();
+        let plaintext = open(&KEY, &sealed).unwrap();
+        assert!(split_name(&sealed, &plaintext).is_err());
+
+        assert!(seal_named(&KEY, &[b'a'; 65536], b"", None).is_err());
+    }
+
+    #[test]
+    fn test_rejected() {
+        let sealed = seal(&KEY, b"plaintext", None).unwrap();
+
+        assert!(matches!(
+            open(&[8; 32], &sealed),
+            Err(FormatError::Authentication)
+        ));
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(open(&KEY, &tampered).is_err());
+        }
+        for len in 0..sealed.len() {
+            assert!(open(&KEY, &sealed[..len]).is_err());
+        }
+
+        let mut newer = sealed;
+        newer[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            open(&KEY, &newer),
+            Err(FormatError::UnsupportedVersion(2))
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+//! Matching of paths against shell-like patterns. `*` matches any run of characters and `?` any
+//! single one, but neither matches `/`. `[...]` matches one of a set of characters, like `[abc]`
+//! or `[a-z]`, or one outside of it, like `[!abc]`. A component which is only `**` matches any
+//! number of directories, and `\` takes the character after it literally.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Where bytes which are not UTF-8 are placed among characters, past the last one, so that they
+/// only match themselves.
+const NOT_UTF8: u32 = 0x11_0000;
+
+/// Splits the character at the start of `s` off the rest, as its code point. Bytes which are not
+/// UTF-8 are taken one at a time, as `NOT_UTF8` plus the byte.
+fn split_char(s: &[u8]) -> Option<(u32, &[u8])> {
+    let len = match *s.first()? {
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        0xc0..=0xdf => 2,
+        _ => 1,
+    };
+
+    match s.get(..len).map(std::str::from_utf8) {
+        Some(Ok(c)) => Some((c.chars().next()? as u32, &s[len..])),
+        _ => Some((NOT_UTF8 + s[0] as u32, &s[1..])),
+    }
+}
+
+/// The characters a `[...]` pattern matches.
+struct Set {
+    negated: bool,
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Set {
+    /// Bytes which are not UTF-8 are in no set, nor outside of one.
+    fn contains(&self, c: u32) -> bool {
+        c < NOT_UTF8 && self.ranges.iter().any(|&(a, b)| a <= c && c <= b) != self.negated
+    }
+}
+
+/// Parses a `[...]` pattern which starts after the `[`, returning its set and the rest of the
+/// pattern. Returns `None` if the set is never closed, in which case the `[` is taken literally.
+fn parse_set(pattern: &[u8]) -> Option<(Set, &[u8])> {
+    let (negated, mut rest) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        let (c, after) = match split_char(rest)? {
+            // A `]` right at the start is part of the set.
+            (c, after) if c == ']' as u32 && !first => {
+                return Some((Set { negated, ranges }, after))
+            }
+            (c, after) if c == '\\' as u32 => split_char(after)?,
+            next => next,
+        };
+        first = false;
+
+        match after.strip_prefix(b"-") {
+            Some(end) if !end.is_empty() && !end.starts_with(b"]") => {
+                let (end, end_rest) = match split_char(end)? {
+                    (end, end_rest) if end == '\\' as u32 => split_char(end_rest)?,
+                    next => next,
+                };
+                ranges.push((c, end));
+                rest = end_rest;
+            }
+            _ => {
+                ranges.push((c, c));
+                rest = after;
+            }
+        }
+    }
+}
+
+/// Matches one component of a path against one component of a pattern.
+fn matches_name(pattern: &[u8], name: &[u8]) -> bool {
+    let (p, rest) = match split_char(pattern) {
+        Some(next) => next,
+        None => return name.is_empty(),
+    };
+
+    if p == '*' as u32 {
+        let mut name = name;
+        while !matches_name(rest, name) {
+            match split_char(name) {
+                Some((_, after)) => name = after,
+                None => return false,
+            }
+        }
+        return true;
+    }
+    let (c, name) = match split_char(name) {
+        Some(next) => next,
+        None => return false,
+    };
+
+    let (matched, rest) = match char::from_u32(p) {
+        Some('?') => (true, rest),
+        Some('[') => match parse_set(rest) {
+            Some((set, rest)) => (set.contains(c), rest),
+            None => (c == p, rest),
+        },
+        Some('\\') => match split_char(rest) {
+            Some((p, rest)) => (c == p, rest),
+            None => (c == p, rest),
+        },
+        _ => (c == p, rest),
+    };
+
+    matched && matches_name(rest, name)
+}
+
+fn matches_components(pattern: &[&[u8]], path: &[&[u8]]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&b"**", rest)) => (0..=path.len()).any(|i| matches_components(rest, &path[i..])),
+        Some((p, rest)) => match path.split_first() {
+            Some((name, path)) => matches_name(p, name) && matches_components(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Returns whether the whole of the relative `path` matches `pattern`. Empty and `.` components
+/// of the pattern are ignored, so `./a/` matches `a`.
+pub fn matches(pattern: &OsStr, path: &Path) -> bool {
+    let pattern = pattern
+        .as_bytes()
+        .split(|&b| b == b'/')
+        .filter(|p| !p.is_empty() && *p != b".")
+        .collect::<Vec<_>>();
+    let path = path.iter().map(|c| c.as_bytes()).collect::<Vec<_>>();
+
+    matches_components(&pattern, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(pattern: &str, matching: &[&str], other: &[&str]) {
+        let pattern = OsStr::new(pattern);
+        for path in matching {
+            assert!(matches(pattern, Path::new(path)), "{:?} {}", pattern, path);
+        }
+        for path in other {
+            assert!(!matches(pattern, Path::new(path)), "{:?} {}", pattern, path);
+        }
+    }
+
+    #[test]
+    fn test_wildcards() {
+        check(
+            "*.txt",
+            &["a.txt", ".txt"],
+            &["a.txt.gz", "d/a.txt", "a.TXT"],
+        );
+        check("a?c", &["abc", "a\u{e9}c"], &["ac", "abbc", "a/c"]);
+        check("d/*", &["d/a", "d/.b"], &["d", "d/a/b", "e/a"]);
+        check("*", &["a", "\u{e9}t\u{e9}"], &["a/b"]);
+        check("./d//a/", &["d/a"], &["d", "d/a/b"]);
+    }
+
+    #[test]
+    fn test_double_star() {
+        check("**", &["a", "a/b/c"], &[]);
+        check("**/c", &["c", "a/c", "a/b/c"], &["a/b", "c/d"]);
+        check("a/**/c", &["a/c", "a/b/c", "a/b/b/c"], &["b/c", "a/cc"]);
+        check("a/**", &["a", "a/b", "a/b/c"], &["b"]);
+        // Only a whole component is a recursive wildcard.
+        check("a**/c", &["ab/c"], &["ab/b/c"]);
+    }
+
+    #[test]
+    fn test_sets() {
+        check("[abc]", &["a", "c"], &["d", "ab", ""]);
+        check("[a-z]x", &["mx"], &["Mx", "1x"]);
+        check("[!a-z]", &["M", "1", "\u{e9}"], &["m", "MM"]);
+        check("[^0-9]", &["a"], &["5"]);
+        check("[]a]", &["]", "a"], &["b"]);
+        check("[a-]", &["a", "-"], &["b"]);
+        check("[\\]]", &["]"], &["\\"]);
+        check("[\u{e0}-\u{ff}]", &["\u{e9}"], &["e"]);
+        // Never closed, so taken literally.
+        check("[ab", &["[ab"], &["a"]);
+    }
+
+    #[test]
+    fn test_escapes() {
+        check("\\*", &["*"], &["a"]);
+        check("a\\?", &["a?"], &["ab"]);
+        check("\\[a]", &["[a]"], &["a"]);
+        check("a\\", &["a\\"], &["a"]);
+        check("\\\\", &["\\"], &["\\\\"]);
+    }
+
+    #[test]
+    fn test_not_utf8() {
+        let bytes = |bytes: &'static [u8]| OsStr::from_bytes(bytes);
+        let path = |b| Path::new(bytes(b));
+
+        assert!(matches(bytes(b"*"), path(b"\xff")));
+        assert!(matches(bytes(b"a?b"), path(b"a\xffb")));
+        assert!(matches(bytes(b"*.txt"), path(b"\xc3\x28.txt")));
+        assert!(matches(bytes(b"d/**"), path(b"d/\xfe/\xff")));
+        assert!(!matches(bytes(b"a[!b]b"), path(b"a\xffb")));
+        assert!(!matches(OsStr::new("\u{ff}"), path(b"\xff")));
+        // A truncated character is as many bytes.
+        assert!(matches(bytes(b"???"), path(b"\xe2\x82x")));
+        // Such bytes in a pattern match themselves.
+        assert!(matches(bytes(b"\xff*"), path(b"\xff.txt")));
+        assert!(!matches(bytes(b"\xff"), path(b"\xfe")));
+        assert!(matches(bytes(b"a\\\xff"), path(b"a\xff")));
+    }
+
+    #[test]
+    fn test_long_names() {
+        let name = "a".repeat(10_000);
+        check("*a", &[&name], &[]);
+        check("*b", &[], &[&name]);
+    }
+}
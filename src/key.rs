@@ -0,0 +1,344 @@
+//! Reading the Crypt key, either interactively or from one of the non-interactive sources.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem::ManuallyDrop;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use libcrypt::secret::Zeroizing;
+use libcrypt::stdin_extras::{Input, Tty};
+use libcrypt::strength;
+
+use crate::Mode;
+
+/// Where the key is read from.
+pub enum KeySource {
+    /// Prompts for the key on the terminal.
+    Prompt,
+    /// Reads the contents of a file.
+    File(PathBuf),
+    /// Reads an environment variable.
+    Env(OsString),
+    /// Reads the first line from an already opened file descriptor.
+    Fd(i32),
+    /// Reads the first line from `Stdin`.
+    Stdin,
+}
+
+/// Reads the key from `source`. When encrypting with a key typed at the prompt, the key has to
+/// be entered twice, since a mistake would make the data unrecoverable. Keys are always checked
+/// for strength when encrypting. The prompt gives up after `timeout` without input.
+pub fn read_key(
+    source: &KeySource,
+    mode: &Mode,
+    allow_weak_key: bool,
+    timeout: Option<Duration>,
+) -> io::Result<Zeroizing<Vec<u8>>> {
+    let new = matches!(mode, Mode::ENCRYPT);
+    read(source, "Crypt key", new, allow_weak_key, timeout)
+}
+
+/// Reads the key replacing the current one from `source`, like a key to encrypt with.
+pub fn read_new_key(
+    source: &KeySource,
+    allow_weak_key: bool,
+    timeout: Option<Duration>,
+) -> io::Result<Zeroizing<Vec<u8>>> {
+    read(source, "new Crypt key", true, allow_weak_key, timeout)
+}
+
+/// Reads the key `name` refers to at the prompt from `source`, confirming it and checking its
+/// strength if it is `new`.
+fn read(
+    source: &KeySource,
+    name: &str,
+    new: bool,
+    allow_weak_key: bool,
+    timeout: Option<Duration>,
+) -> io::Result<Zeroizing<Vec<u8>>> {
+    let key = match source {
+        KeySource::Prompt => {
+            let mut tty = Tty::open_with_timeout(timeout)?;
+            let key = tty.input_masked(&format!("Enter {}:", name), '*')?;
+            // Checked before asking to confirm a key which is refused anyway.
+            check_key(key.as_bytes(), new, allow_weak_key)?;
+
+            if new && *tty.input_masked(&format!("Confirm {}:", name), '*')? != *key {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the keys do not match",
+                ));
+            }
+
+            return Ok(key.into_bytes());
+        }
+        KeySource::File(path) => {
+            let mode = fs::metadata(path)?.permissions().mode();
+            if mode & 0o077 != 0 {
+                eprintln!(
+                    "Warning: the key file '{}' can be read by other users (mode {:o}).",
+                    path.display(),
+                    mode & 0o777,
+                );
+            }
+
+            let mut key = Zeroizing::new(fs::read(path)?);
+            trim_newline(&mut key);
+            key
+        }
+        KeySource::Env(var) => {
+            eprintln!(
+                "Warning: environment variables can be read by other processes of the same user \
+                 and are inherited by child processes."
+            );
+
+            env_key(var)?
+        }
+        KeySource::Fd(fd) => {
+            if unsafe { libc::fcntl(*fd, libc::F_GETFD) } == -1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("file descriptor {} is not open", fd),
+                ));
+            }
+
+            // The descriptor belongs to the caller, so it is left open.
+            let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(*fd) });
+            read_first_line(&mut *file)?
+        }
+        KeySource::Stdin => read_first_line(io::stdin().lock())?,
+    };
+
+    check_key(&key, new, allow_weak_key)?;
+    Ok(key)
+}
+
+/// Fails if `key` is empty, or if it is `new` and too weak.
+fn check_key(key: &[u8], new: bool, allow_weak_key: bool) -> io::Result<()> {
+    if key.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the key is empty"));
+    }
+    if new {
+        // A copy, zeroed along with the others.
+        let key = Zeroizing::new(String::from_utf8_lossy(key).into_owned());
+        check_strength(&key, allow_weak_key)?;
+    }
+
+    Ok(())
+}
+
+fn env_key(var: &OsStr) -> io::Result<Zeroizing<Vec<u8>>> {
+    use std::os::unix::ffi::OsStringExt;
+
+    match std::env::var_os(var) {
+        Some(key) => Ok(Zeroizing::new(key.into_vec())),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "the environment variable '{}' is not set",
+                var.to_string_lossy()
+            ),
+        )),
+    }
+}
+
+/// Reads the first line from `reader` one byte at a time, so that whatever follows it is left
+/// for whoever reads next.
+fn read_first_line<R: Read>(mut reader: R) -> io::Result<Zeroizing<Vec<u8>>> {
+    let mut key = Zeroizing::new(Vec::with_capacity(1024));
+    let mut byte = Zeroizing::new(vec![0]);
+
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => {
+                key.push_secure(&byte);
+                if byte[0] == b'\n' {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    trim_newline(&mut key);
+    Ok(key)
+}
+
+fn trim_newline(key: &mut Vec<u8>) {
+    if key.ends_with(b"\n") {
+        key.pop();
+        if key.ends_with(b"\r") {
+            key.pop();
+        }
+    }
+}
+
+/// Warns about keys which are somewhat easy to guess, and refuses the ones which are very easy
+/// to guess unless `allow_weak_key` is set.
+fn check_strength(key: &str, allow_weak_key: bool) -> io::Result<()> {
+    let estimate = strength::estimate(key);
+
+    if estimate.score > 2 {
+        return Ok(());
+    }
+
+    eprintln!(
+        "Warning: this key is {} to guess (about 10^{:.0} guesses).",
+        if estimate.score < 2 { "very easy" } else { "easy" },
+        estimate.guesses_log10,
+    );
+    if let Some(warning) = estimate.warning {
+        eprintln!("  {}", warning);
+    }
+    for suggestion in &estimate.suggestions {
+        eprintln!("  - {}", suggestion);
+    }
+
+    if estimate.score < 2 && !allow_weak_key {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "refusing to encrypt with a weak key (use --allow-weak-key to override)",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fn read(source: &KeySource) -> io::Result<Vec<u8>> {
+        read_key(source, &Mode::DECRYPT, false, None).map(|key| key.to_vec())
+    }
+
+    /// Returns the reading end of a pipe holding `data`.
+    fn pipe(data: &[u8]) -> i32 {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let n = unsafe { libc::write(fds[1], data.as_ptr() as *const libc::c_void, data.len()) };
+        assert_eq!(n, data.len() as isize);
+        unsafe { libc::close(fds[1]) };
+        fds[0]
+    }
+
+    #[test]
+    fn test_trim_newline() {
+        for (line, exp) in [
+            (&b"key\n"[..], &b"key"[..]),
+            (b"key\r\n", b"key"),
+            (b"key", b"key"),
+            (b"key\r", b"key\r"),
+            (b"key\n\n", b"key\n"),
+            (b"\n", b""),
+        ]
+        .iter()
+        {
+            let mut key = line.to_vec();
+            trim_newline(&mut key);
+            assert_eq!(key, *exp);
+        }
+    }
+
+    #[test]
+    fn test_read_first_line() {
+        let first = |data: &[u8]| read_first_line(data).unwrap().to_vec();
+
+        assert_eq!(first(b"key\nsecond line\n"), b"key");
+        assert_eq!(first(b"key\r\n"), b"key");
+        assert_eq!(first(b"no newline"), b"no newline");
+        assert_eq!(first(b""), b"");
+    }
+
+    #[test]
+    fn test_file() {
+        let path = env::temp_dir().join(format!("crypt-test-{}-key", std::process::id()));
+        let write = |data: &[u8]| {
+            let _ = fs::remove_file(&path);
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true).mode(0o600);
+            io::Write::write_all(&mut options.open(&path).unwrap(), data).unwrap();
+        };
+
+        // Only a single newline is taken off the end, the rest of the file is the key.
+        write(b"file key\nsecond line\n");
+        let key = read(&KeySource::File(path.clone()));
+        assert_eq!(key.unwrap(), b"file key\nsecond line");
+
+        write(b"\n");
+        assert!(read(&KeySource::File(path.clone())).is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(read(&KeySource::File(path)).is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        env::set_var("CRYPT_TEST_KEY", "env key");
+        assert_eq!(read(&KeySource::Env("CRYPT_TEST_KEY".into())).unwrap(), b"env key");
+
+        env::set_var("CRYPT_TEST_EMPTY_KEY", "");
+        assert!(read(&KeySource::Env("CRYPT_TEST_EMPTY_KEY".into())).is_err());
+
+        let e = read(&KeySource::Env("CRYPT_TEST_UNSET_KEY".into())).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_weak_env_key() {
+        env::set_var("CRYPT_TEST_WEAK_KEY", "password");
+        let source = KeySource::Env("CRYPT_TEST_WEAK_KEY".into());
+
+        assert!(read_key(&source, &Mode::ENCRYPT, false, None).is_err());
+        assert!(read_key(&source, &Mode::ENCRYPT, true, None).is_ok());
+    }
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key(b"", false, true).is_err());
+        assert!(check_key(b"", true, true).is_err());
+        assert!(check_key(b"password", false, false).is_ok());
+        assert!(check_key(b"password", true, false).is_err());
+        assert!(check_key(b"password", true, true).is_ok());
+    }
+
+    #[test]
+    fn test_fd() {
+        let fd = pipe(b"fd key\r\nrest");
+        assert_eq!(read(&KeySource::Fd(fd)).unwrap(), b"fd key");
+
+        // The descriptor is left open, with what follows the key unread.
+        let mut rest = [0; 8];
+        let n = unsafe { libc::read(fd, rest.as_mut_ptr() as *mut libc::c_void, rest.len()) };
+        assert_eq!(&rest[..n as usize], b"rest");
+        unsafe { libc::close(fd) };
+
+        let e = read(&KeySource::Fd(-1)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_stdin() {
+        // Nothing else in the tests reads stdin, so it can be swapped for a pipe for a while.
+        let fd = pipe(b"stdin key\n");
+        let key = unsafe {
+            let stdin = libc::dup(0);
+            libc::dup2(fd, 0);
+            libc::close(fd);
+            let key = read(&KeySource::Stdin);
+            libc::dup2(stdin, 0);
+            libc::close(stdin);
+            key
+        };
+
+        assert_eq!(key.unwrap(), b"stdin key");
+    }
+}
@@ -0,0 +1,155 @@
+//! The key slot, which holds the random key data is encrypted with, itself sealed with a key
+//! derived from the password, so that the password can be changed by sealing the key again
+//! instead of encrypting everything again.
+//!
+//! ```text
+//! "CRYPTKY" | version (1 byte) | iterations (4 bytes) | salt (16 bytes) | sealed key
+//! ```
+//!
+//! The key is sealed in the format of `format`, with a key derived from the password and the salt
+//! by PBKDF2-HMAC-SHA256 with the given number of iterations, which are authenticated along with
+//! it since any other salt or number of iterations gives another key. The slot is written before
+//! a single file, an archive or a packed file, or before the sealed index or first blob stored
+//! as '00' in a directory, and everything after it is in the same format as without it.
+
+use std::convert::TryInto;
+use std::io;
+
+use libcrypt::aead::TAG_LEN;
+use libcrypt::pbkdf2;
+use libcrypt::random;
+use libcrypt::secret::Zeroizing;
+
+use crate::format::{self, FormatError};
+
+pub const MAGIC: &[u8] = b"CRYPTKY";
+pub const VERSION: u8 = 1;
+/// How many iterations new slots derive their key with.
+pub const ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + SALT_LEN;
+/// The key is sealed unpadded, which adds a single byte of padding.
+pub const LEN: usize = HEADER_LEN + format::HEADER_LEN + KEY_LEN + 1 + TAG_LEN;
+
+/// Splits the key slot off the start of `data`, if it has one, returning it along with the rest.
+pub fn split(data: &[u8]) -> (Option<&[u8]>, &[u8]) {
+    match data.starts_with(MAGIC) && data.len() >= LEN {
+        true => (Some(&data[..LEN]), &data[LEN..]),
+        false => (None, data),
+    }
+}
+
+/// Returns a new random key to encrypt data with, along with a slot sealing it with `password`.
+pub fn new(password: &[u8]) -> io::Result<(Zeroizing<Vec<u8>>, Vec<u8>)> {
+    let mut key = Zeroizing::new(vec![0; KEY_LEN]);
+    random::fill(&mut key)?;
+    let slot = seal(password, &key)?;
+
+    Ok((key, slot))
+}
+
+/// Seals `key` into a new slot with `password`, under a fresh salt.
+pub fn seal(password: &[u8], key: &[u8]) -> io::Result<Vec<u8>> {
+    seal_with(password, key, ITERATIONS)
+}
+
+fn seal_with(password: &[u8], key: &[u8], iterations: u32) -> io::Result<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    random::fill(&mut salt)?;
+
+    let mut slot = Vec::with_capacity(LEN);
+    slot.extend_from_slice(MAGIC);
+    slot.push(VERSION);
+    slot.extend_from_slice(&iterations.to_le_bytes());
+    slot.extend_from_slice(&salt);
+    slot.extend_from_slice(&format::seal(
+        &derive(password, &salt, iterations),
+        key,
+        None,
+    )?);
+
+    Ok(slot)
+}
+
+/// Opens `slot` with `password`, returning the key data is encrypted with.
+pub fn open(password: &[u8], slot: &[u8]) -> Result<Zeroizing<Vec<u8>>, FormatError> {
+    if slot[MAGIC.len()] != VERSION {
+        return Err(FormatError::UnsupportedVersion(slot[MAGIC.len()]));
+    }
+
+    // Anything far from what crypt writes is a corrupted slot, which would otherwise take over
+    // an hour to derive a key for.
+    let iterations = u32::from_le_bytes(slot[MAGIC.len() + 1..][..4].try_into().unwrap());
+    if !(ITERATIONS / 16..=ITERATIONS * 16).contains(&iterations) {
+        return Err(FormatError::Authentication);
+    }
+    let salt = &slot[MAGIC.len() + 5..HEADER_LEN];
+    let key = format::open(&derive(password, salt, iterations), &slot[HEADER_LEN..])?;
+
+    Ok(Zeroizing::new(key))
+}
+
+fn derive(password: &[u8], salt: &[u8], iterations: u32) -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0; KEY_LEN]);
+    pbkdf2::pbkdf2_sha256(password, salt, iterations, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The fewest iterations `open` accepts, which keeps the tests fast.
+    const FEW: u32 = ITERATIONS / 16;
+
+    #[test]
+    fn test_round_trip() {
+        let slot = seal_with(b"password", b"0123456789abcdef0123456789abcdef", FEW).unwrap();
+        assert_eq!(slot.len(), LEN);
+        assert_eq!(
+            &open(b"password", &slot).unwrap()[..],
+            b"0123456789abcdef0123456789abcdef"
+        );
+
+        let data = [&slot[..], b"rest"].concat();
+        assert_eq!(split(&data), (Some(&slot[..]), &b"rest"[..]));
+        assert_eq!(split(b"rest"), (None, &b"rest"[..]));
+        assert_eq!(split(&slot[..LEN - 1]), (None, &slot[..LEN - 1]));
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let slot = seal_with(b"password", &[7; KEY_LEN], FEW).unwrap();
+        assert!(matches!(
+            open(b"passwork", &slot),
+            Err(FormatError::Authentication)
+        ));
+    }
+
+    #[test]
+    fn test_tampered() {
+        let slot = seal_with(b"password", &[7; KEY_LEN], FEW).unwrap();
+
+        // Other iterations give another key, and ones out of bounds are not even tried.
+        for iterations in [FEW + 1, FEW - 1, ITERATIONS * 16 + 1, u32::MAX, 0].iter() {
+            let mut tampered = slot.clone();
+            tampered[MAGIC.len() + 1..][..4].copy_from_slice(&iterations.to_le_bytes());
+            assert!(matches!(
+                open(b"password", &tampered),
+                Err(FormatError::Authentication)
+            ));
+        }
+
+        let mut tampered = slot.clone();
+        tampered[HEADER_LEN - 1] ^= 1;
+        assert!(open(b"password", &tampered).is_err());
+
+        let mut tampered = slot;
+        tampered[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            open(b"password", &tampered),
+            Err(FormatError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+//! Decryption of files and directories written by crypt 1.0, before the data was authenticated.
+//!
+//! The whole input was encrypted with one ChaCha20 stream, whose nonce was generated from the key
+//! itself. Since nothing was authenticated, a wrong key cannot be detected and just results in
+//! garbage.
+
+use std::path::Path;
+use std::{fs, io};
+
+use libcrypt::chacha::ChaCha;
+use libcrypt::mersenne_twister::Generator;
+
+use crate::{atomic, dir};
+
+fn cipher(key: &[u8]) -> ChaCha {
+    let mut gen = Generator::from(&key.to_vec());
+
+    ChaCha::from_state(
+        key,
+        0,
+        [gen.next() as u32, gen.next() as u32, gen.next() as u32],
+    )
+}
+
+/// Decrypts the contents of a single file.
+pub fn decrypt_file(key: &[u8], contents: &[u8]) -> Vec<u8> {
+    cipher(key).decrypt(contents)
+}
+
+/// Returns whether `name` is what crypt 1.0 named the files of a directory, a counter in hex.
+fn is_blob_name(name: &str) -> bool {
+    name.len() >= 2 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'A'..=b'F'))
+}
+
+/// Decrypts the directory `input` into `output`.
+pub fn decrypt_dir(key: &[u8], input: &Path, output: &Path) -> io::Result<()> {
+    let mut cc = cipher(key);
+    let meta = String::from_utf8(cc.decrypt(&fs::read(input.join("00"))?))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "wrong key"))?;
+
+    let temp = atomic::Temp::dir(output)?;
+    let temp_out = temp.path();
+
+    let meta = meta.split('\n').collect::<Vec<&str>>();
+    if meta.len() % 2 == 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "wrong key"));
+    }
+    let mut data;
+
+    for line in (0..(meta.len())).step_by(2) {
+        let name = meta[line];
+        if !is_blob_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the index names a file which is not part of it: '{}'", name),
+            ));
+        }
+        data = cc.decrypt(&fs::read(input.join(name))?);
+
+        // crypt 1.0 stored paths with the leading '/' left from the input.
+        let path = dir::entry_path(temp_out, Path::new(meta[line + 1].trim_start_matches('/')))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        atomic::write(&path, &data)?;
+    }
+
+    temp.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{self, TempDir, KEY};
+
+    /// Encrypts `files` the way crypt 1.0 did into the directory `output`, listing each under
+    /// the given blob name and path.
+    fn encrypted(files: &[(&str, &str, &[u8])], output: &Path) {
+        let mut cc = cipher(&KEY);
+        let meta = files
+            .iter()
+            .map(|(name, path, _)| format!("{}\n{}", name, path))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        fs::create_dir_all(output).unwrap();
+        fs::write(output.join("00"), cc.encrypt(meta.as_bytes())).unwrap();
+        for (name, _, contents) in files {
+            // Names leading elsewhere are only listed.
+            let _ = fs::write(output.join(name), cc.encrypt(contents));
+        }
+    }
+
+    #[test]
+    fn test_decrypt_dir() {
+        let dir = TempDir::new("legacy-dir");
+        let enc = dir.join("enc");
+        encrypted(&[("01", "/a", b"alpha"), ("02", "/sub/b", b"beta")], &enc);
+
+        let out = dir.join("out");
+        decrypt_dir(&KEY, &enc, &out).unwrap();
+        let tree = test::tree(&out);
+        let tree = tree
+            .iter()
+            .map(|(p, c)| (p.to_str().unwrap(), c.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tree,
+            [("", "/"), ("a", "alpha"), ("sub", "/"), ("sub/b", "beta")]
+        );
+    }
+
+    #[test]
+    fn test_outside() {
+        let dir = TempDir::new("legacy-outside");
+
+        for (name, path) in [
+            ("01", "/../escaped"),
+            ("01", "/sub/../../escaped"),
+            ("01", "/"),
+            ("../enc/01", "/a"),
+            ("01/../../secret", "/a"),
+            ("1", "/a"),
+            ("0x", "/a"),
+        ]
+        .iter()
+        {
+            let enc = dir.join("enc");
+            let _ = fs::remove_dir_all(&enc);
+            encrypted(&[(name, path, b"escaped")], &enc);
+
+            let out = dir.join("out");
+            assert!(decrypt_dir(&KEY, &enc, &out).is_err(), "{} {}", name, path);
+            assert!(fs::symlink_metadata(&out).is_err());
+            assert!(fs::symlink_metadata(dir.join("escaped")).is_err());
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Listing of what an encrypted directory holds, from its index alone.
+
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+use crate::dir::{Entry, Kind};
+
+/// How entries are printed.
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// The size, modification time and path of every entry.
+    Short,
+    /// Also the permissions and owner of every entry, like `ls -l`.
+    Long,
+    /// Everything in the index as a JSON array.
+    Json,
+}
+
+/// Formats `time` as a UTC date, like `2024-05-01 12:34`.
+fn date(time: i64) -> String {
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+
+    match unsafe { libc::gmtime_r(&(time as libc::time_t), &mut tm) }.is_null() {
+        true => String::from("?"),
+        false => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min
+        ),
+    }
+}
+
+/// Formats the kind and permissions of an entry, like `drwxr-xr-x`.
+fn mode(kind: Kind, mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match kind {
+        Kind::File => '-',
+        Kind::Dir => 'd',
+        Kind::Symlink => 'l',
+    });
+
+    // The special bit of each class replaces its execute bit: setuid, setgid, then sticky.
+    for (shift, special, set) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')].iter() {
+        let bits = mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => *set,
+            (false, true) => set.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    s
+}
+
+/// Returns the path of `e` with backslashes, control and bidirectional formatting characters, and
+/// bytes which are not UTF-8 escaped, so that a path cannot pass for anything else on the line or
+/// for more lines.
+fn path(e: &Entry) -> String {
+    let mut path = String::new();
+
+    for chunk in e.path.as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => path.push_str("\\\\"),
+                c if c.is_control() => path.extend(c.escape_default()),
+                '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' => {
+                    path.extend(c.escape_unicode())
+                }
+                c => path.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            path.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+
+    if e.kind == Kind::Dir {
+        path.push('/');
+    }
+    path
+}
+
+fn size(e: &Entry) -> String {
+    match e.size {
+        Some(size) => size.to_string(),
+        None => String::from("-"),
+    }
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn json_entry(e: &Entry) -> String {
+    let kind = match e.kind {
+        Kind::File => "file",
+        Kind::Dir => "directory",
+        Kind::Symlink => "symlink",
+    };
+    let size = match e.size {
+        Some(size) => size.to_string(),
+        None => String::from("null"),
+    };
+    let m = &e.meta;
+    let meta = format!(
+        "\"mode\":{},\"uid\":{},\"gid\":{},\"mtime\":{},\"mtime_nsec\":{}",
+        m.mode, m.uid, m.gid, m.mtime, m.mtime_nsec
+    );
+
+    // Paths which are not UTF-8 cannot be JSON strings, so their bytes are given as well.
+    let bytes = match std::str::from_utf8(e.path.as_os_str().as_bytes()) {
+        Ok(_) => String::new(),
+        Err(_) => format!(",\"path_bytes\":{:?}", e.path.as_os_str().as_bytes()),
+    };
+
+    format!(
+        "{{\"path\":{}{},\"kind\":\"{}\",\"size\":{},{}}}",
+        json_string(&e.path.to_string_lossy()),
+        bytes,
+        kind,
+        size,
+        meta
+    )
+}
+
+/// Prints `entries` to `out` in `format`.
+pub fn print<W: Write>(entries: &[Entry], format: Format, out: &mut W) -> io::Result<()> {
+    if let Format::Json = format {
+        let entries = entries.iter().map(json_entry).collect::<Vec<_>>();
+        return writeln!(out, "[{}]", entries.join(","));
+    }
+
+    for e in entries {
+        let m = &e.meta;
+        let time = date(m.mtime);
+
+        match format {
+            Format::Long => writeln!(
+                out,
+                "{} {:>5} {:>5} {:>10} {} {}",
+                mode(e.kind, m.mode),
+                m.uid,
+                m.gid,
+                size(e),
+                time,
+                path(e)
+            )?,
+            _ => writeln!(out, "{:>10} {} {}", size(e), time, path(e))?,
+        }
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+
+    use crate::dir::Meta;
+
+    fn entry(kind: Kind, path: &[u8]) -> Entry {
+        let file = kind != Kind::Dir;
+
+        Entry {
+            number: Some(1).filter(|_| file),
+            nonce: None,
+            kind,
+            size: Some(12).filter(|_| file),
+            hash: None,
+            path: PathBuf::from(OsStr::from_bytes(path)),
+            meta: Meta {
+                mode: 0o644,
+                uid: 1000,
+                gid: 100,
+                mtime: 86400,
+                mtime_nsec: 0,
+            },
+        }
+    }
+
+    fn printed(entries: &[Entry], format: Format) -> String {
+        let mut out = Vec::new();
+        print(entries, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_escaped_paths() {
+        let exp: [(&[u8], &str); 7] = [
+            (b"plain/path", "plain/path"),
+            ("caf\u{e9}".as_bytes(), "caf\u{e9}"),
+            (b"two\nlines", "two\\nlines"),
+            (b"back\\slash", "back\\\\slash"),
+            (b"\x1b[2Jtab\t", "\\u{1b}[2Jtab\\t"),
+            (b"bytes\xff\xfe", "bytes\\xff\\xfe"),
+            ("txt.\u{202e}exe".as_bytes(), "txt.\\u{202e}exe"),
+        ];
+
+        for (raw, escaped) in exp.iter() {
+            assert_eq!(path(&entry(Kind::File, raw)), *escaped);
+        }
+        assert_eq!(path(&entry(Kind::Dir, b"dir")), "dir/");
+    }
+
+    #[test]
+    fn test_forged_lines() {
+        let entries = [entry(Kind::File, b"a\n        12 1970-01-02 00:00 b")];
+
+        for format in [Format::Short, Format::Long].iter() {
+            assert_eq!(printed(&entries, *format).lines().count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let entries = [entry(Kind::Dir, b"d"), entry(Kind::File, b"d/f")];
+
+        assert_eq!(
+            printed(&entries, Format::Short),
+            "         - 1970-01-02 00:00 d/\n        12 1970-01-02 00:00 d/f\n"
+        );
+        assert_eq!(
+            printed(&entries, Format::Long),
+            "drw-r--r--  1000   100          - 1970-01-02 00:00 d/\n\
+             -rw-r--r--  1000   100         12 1970-01-02 00:00 d/f\n"
+        );
+
+        let json = printed(&[entry(Kind::File, b"x\xff")], Format::Json);
+        assert!(json.contains("\"path\":\"x\u{fffd}\",\"path_bytes\":[120, 255]"));
+        assert!(json.contains("\"kind\":\"file\",\"size\":12,\"mode\":420"));
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(mode(Kind::Dir, 0o1777), "drwxrwxrwt");
+        assert_eq!(mode(Kind::File, 0o4755), "-rwsr-xr-x");
+        assert_eq!(mode(Kind::File, 0o2644), "-rw-r-Sr--");
+        assert_eq!(mode(Kind::Symlink, 0o777), "lrwxrwxrwx");
+    }
+}
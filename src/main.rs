@@ -1,206 +1,1336 @@
-use std::{env, fs, io, process};
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fs, process};
 
-use libcrypt::chacha::ChaCha;
-use libcrypt::mersenne_twister::Generator;
-use libcrypt::stdin_extras::Input;
+use libcrypt::hardening;
+use libcrypt::padding::Padding;
+use libcrypt::secret::Zeroizing;
+use libcrypt::stdin_extras::{Input, Tty};
+
+mod archive;
+mod atomic;
+mod dir;
+mod edit;
+mod format;
+mod glob;
+mod key;
+mod keyslot;
+mod legacy;
+mod list;
+mod packed;
+mod sandbox;
+mod shred;
+mod test;
+mod verify;
+
+use format::FormatError;
+use key::KeySource;
 
 fn help() -> ! {
     println!(
-        "Crypt v1.0.0
+        "Crypt v2.0.0
 
 Usage:
-    crypt <MODE> <INPUT> [OUTPUT]
+    crypt <MODE> [OPTIONS] <INPUT> [OUTPUT]
+    crypt extract [OPTIONS] <INPUT> <PATH>...
+    crypt add|update|remove [OPTIONS] <INPUT> <PATH>...
+    crypt sync [OPTIONS] <SRC> <DEST>
+    crypt passwd [OPTIONS] <INPUT>
+    crypt verify [OPTIONS] <INPUT>
+    crypt list [OPTIONS] <INPUT>
 
-Crypt uses ChaCha20 to encrypt/decrypt your files.
+Crypt uses ChaCha20-Poly1305 to encrypt/decrypt your files, so a wrong key or corrupted
+data is detected when decrypting.
 
 MODES:
     encrypt    Encrypts INPUT and stores it in OUTPUT. 
     decrypt    Decrypts INPUT and stores it in OUTPUT.
+    extract    Decrypts only what matches one of the PATHs in the encrypted directory INPUT,
+               along with the directories leading to it, and stores it in a new directory,
+               which defaults to the same name as with 'decrypt'.
+    add        Encrypts the PATHs into the encrypted directory INPUT, along with everything
+               in them, without changing the rest of it.
+    update     Encrypts the PATHs into the encrypted directory INPUT again, replacing what
+               it holds for them. PATHs which no longer exist are removed from INPUT.
+    remove     Removes what matches one of the PATHs from the encrypted directory INPUT.
+    sync       Makes the encrypted directory DEST hold what the directory SRC holds, only
+               encrypting the files which changed since DEST was written, and prints how
+               many were added, updated, removed or left unchanged. If DEST does not exist,
+               all of SRC is encrypted into it.
+    passwd     Changes the key of the encrypted INPUT, prompting for the current key and then
+               for the new one, without encrypting its data again.
+    verify     Checks that INPUT is intact and that the key is right, without writing anything.
+               Reports every corrupted, missing, extra or reordered entry of a directory, and
+               exits with a nonzero status if there is any.
+    list       Lists the size, modification time and path of everything in the encrypted
+               directory INPUT, decrypting only its index.
     help       Shows this help text.
         
 ARGS:
-    INPUT     The input file to use.
-    OUTPUT    The output file to use.
+    INPUT     The input file to use, or '-' to read from stdin.
+    OUTPUT    The output file to use, or '-' to write to stdout.
               In 'encrypt' mode, will default to 'input.crypt'.
-              In 'decrypt' mode, will default to overwriting the input.
-        
+              In 'decrypt' mode, will default to the original name of the file next to
+              the input, or to the input without its '.crypt' extension.
+              Will default to stdout when reading from stdin.
+              If OUTPUT already exists, crypt asks before overwriting it, and refuses to
+              without '--force' if it cannot ask.
+    PATH      A path in the encrypted directory, as printed by 'list', which may hold the
+              wildcards '*', '?' and '[...]', which do not match '/', and '**', which
+              matches any number of directories. Everything in a matching directory is
+              extracted, and a PATH matching nothing is an error.
+              With 'add' and 'update', a path relative to the source directory, which is
+              stored under the same path in INPUT.
+
+OPTIONS:
+    --allow-weak-key      Encrypt even if the key is estimated to be easy to guess.
+    --archive             Encrypts a directory into a single file, instead of a directory of
+                          encrypted files. Archives are detected when decrypting.
+    --force               Overwrites OUTPUT if it already exists.
+    --json                With 'list', prints everything in the index as JSON.
+    --follow-symlinks     Encrypts what symlinks in a directory lead to, instead of the symlinks
+                          themselves. Symlinks leading back to one of their parents are kept.
+    --key-file <PATH>     Reads the key from a file instead of prompting for it.
+    --key-env <VAR>       Reads the key from an environment variable.
+    --key-fd <N>          Reads the key from the first line of file descriptor N.
+    --new-key-file <PATH>, --new-key-env <VAR>, --new-key-fd <N>
+                          With 'passwd', reads the new key like the options above read the
+                          current one, instead of prompting for it.
+    --output <DIR>        With 'extract', stores what is extracted in DIR.
+    --source <DIR>        With 'add' and 'update', the directory the PATHs are in, which was
+                          encrypted into INPUT (default: the current directory).
+    --long                With 'list', also prints the permissions and owner of every entry.
+    --passphrase-stdin    Reads the key from the first line of stdin.
+    --timeout <SECS>      Gives up if nothing is typed at a prompt for SECS seconds.
+    --retries <N>         Lets a wrong key typed at the prompt be retried N times (default 2).
+    --pack <SIZE>         Packs a directory into encrypted blobs which all hold SIZE bytes, so
+                          only its approximate total size shows. With '--archive', the blobs
+                          are stored in a single file. Packing is detected when decrypting.
+    --pad <SCHEME>        Pads what is encrypted to hide its exact size, either with 'padme',
+                          which adds at most 12%, or up to a multiple of a block size in bytes.
+    --remove-source       Once the output is written and decrypts back to INPUT, overwrites the
+                          files in INPUT with random data and removes it.
+    --dry-run             With '--remove-source', only lists what would be removed.
+    --no-sandbox          Does not restrict crypt to the files it was given.
+
+The key is prompted for on the terminal, even when stdin or stdout are redirected. When
+encrypting, a key typed at the prompt has to be entered twice, and keys which are estimated
+to be easy to guess are refused.
+
+Data is encrypted with a random key, which is stored along with it, encrypted with a key derived
+from your key by PBKDF2-HMAC-SHA256, so 'passwd' only has to encrypt that random key again.
+Copies made before the key was changed still open with the old key and hold the same random
+key, so encrypt INPUT again instead if the old key leaked.
+
+The name of an encrypted file is stored encrypted along with its contents, so it can be given
+back to the file when decrypting, even if the encrypted file was renamed.
+
+Once the arguments are parsed, crypt sandboxes itself with Landlock and seccomp, so it can only
+read INPUT and the key file, and only write next to OUTPUT. With '--follow-symlinks', symlinks
+leading out of a directory being encrypted cannot be followed in the sandbox.
+
+OUTPUT is written next to where it goes under a temporary name, and only takes its place once
+it is complete, so an existing OUTPUT is never lost. Temporary files are removed if crypt fails
+or is interrupted, and crypt offers to remove the ones left behind by a crash.
+
+Overwriting files before removing them does not keep their contents from being recovered on
+copy-on-write or journaling filesystems, nor on SSDs, which may keep the old data elsewhere.
+
+Directories are encrypted along with the permissions, owners and modification times of
+everything in them, which are restored when decrypting. Owners are only restored when running
+as root. The sizes and SHA-256 hashes of the files are stored encrypted as well, so 'sync' can
+tell which files changed without decrypting them.
+
+Environment variables can be read by other processes of the same user, so prefer a key file
+which only you can read, or a file descriptor, when running crypt from scripts.
+
 Examples:
   crypt encrypt foo.txt
     - saves an encrypted version of foo.txt at ./foo.txt.crypt
   crypt e foo.txt secret_msg
     - saves an encrypted version of foo.txt at ./secret_msg
   crypt decrypt foo.txt.crypt
-    - saves a decrypted version of foo.txt.crypt at ./foo.txt.crypt
+    - saves a decrypted version of foo.txt.crypt at ./foo.txt
+  crypt decrypt secret_msg --force
+    - saves a decrypted version of secret_msg at ./foo.txt, overwriting it if it exists
   crypt dec bar.crypt pic.png
     - saves a decrypted version of bar.crypt at ./pic.png
+  crypt encrypt photos/ photos.crypt --archive
+    - saves an encrypted version of the directory photos/ as the single file ./photos.crypt
+  crypt update photos.crypt 2024/trip --source photos
+    - encrypts photos/2024/trip into photos.crypt again, leaving the rest of it as it is
+  crypt sync photos/ photos.crypt
+    - encrypts what changed in photos/ since photos.crypt was written into it
+  crypt passwd photos.crypt
+    - changes the key of photos.crypt, keeping the data encrypted in it as it is
+  cat foo.txt | crypt encrypt - foo.crypt
+    - saves an encrypted version of stdin at ./foo.crypt, still prompting for the key
 "
     );
 
     process::exit(0);
 }
 
-fn main() -> io::Result<()> {
-    let (mode, input, output) = argparse();
+fn main() {
+    if let Err(e) = hardening::disable_core_dumps() {
+        eprintln!("Warning: could not disable core dumps: {}", e);
+    }
+
+    if let Err(e) = atomic::defer_interrupts() {
+        eprintln!("Warning: could not catch interrupts: {}", e);
+    }
+
+    let res = run();
+    // Dies from the signal which interrupted crypt, if any, now that its temporary files are
+    // removed.
+    atomic::reraise_interrupt();
+
+    if let Err(e) = res {
+        eprintln!("crypt: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> io::Result<()> {
+    let args = argparse(env::args_os().skip(1));
+
+    // Before the sandbox, which refuses signalling the processes that left files behind to check
+    // whether they are still running.
+    let stale = match is_std(&args.output) {
+        true => Vec::new(),
+        false => find_stale(&args),
+    };
+
+    if args.sandbox {
+        apply_sandbox(&args)?;
+    }
+
+    remove_stale(&args, stale)?;
+
+    match args.mode {
+        Mode::ENCRYPT => encrypt(&args),
+        Mode::DECRYPT | Mode::EXTRACT => decrypt(&args),
+        Mode::ADD | Mode::UPDATE | Mode::REMOVE => change(&args),
+        Mode::SYNC => sync(&args),
+        Mode::PASSWD => passwd(&args),
+        Mode::VERIFY => verify(&args),
+        Mode::LIST => list(&args),
+    }
+}
+
+fn encrypt(args: &Args) -> io::Result<()> {
+    if args.dry_run {
+        for (path, action) in shred::plan(&args.input)? {
+            println!("Would {} '{}'", action.describe(), path.display());
+        }
+        return Ok(());
+    }
 
-    let key = io::stdin().input_hidden("Enter Crypt key:")?.into_bytes();
+    check_overwrite(args, &args.output)?;
+    if args.remove_source {
+        check_removable(args)?;
+    }
+    let password = key::read_key(
+        &args.key_source,
+        &args.mode,
+        args.allow_weak_key,
+        args.timeout,
+    )?;
+    let (key, slot) = keyslot::new(&password)?;
+
+    let options = dir::Options {
+        follow_symlinks: args.follow_symlinks,
+        padding: args.padding,
+    };
+    write_encrypted(args, &key, &slot, &options)?;
 
-    let mut gen = Generator::from(&key);
-    let mut cc = ChaCha::from_state(
-        &key,
-        0,
-        [gen.next() as u32, gen.next() as u32, gen.next() as u32],
+    if args.remove_source {
+        let plan = shred::plan(&args.input)?;
+        verify_output(args, &key)?;
+        shred::remove(&plan)?;
+    }
+
+    Ok(())
+}
+
+/// Encrypts the input with `key` into the output, with the key `slot` in front of it.
+fn write_encrypted(args: &Args, key: &[u8], slot: &[u8], options: &dir::Options) -> io::Result<()> {
+    if is_std(&args.input) || fs::metadata(&args.input)?.is_file() {
+        let contents = read_input(&args.input)?;
+        let name = match args.input.file_name() {
+            Some(name) if !is_std(&args.input) => name.as_bytes(),
+            _ => b"",
+        };
+        let sealed = format::seal_named(key, name, &contents, args.padding)?;
+
+        // The output might replace the input, so make sure it decrypts back to it first.
+        if format::split_name(&sealed, &format::open(key, &sealed)?)?.1 != &contents[..] {
+            return Err(io::Error::other(
+                "the encrypted output does not decrypt back to the input",
+            ));
+        }
+
+        write_output(&args.output, &[slot, &sealed].concat())
+    } else if let Some(size) = args.pack {
+        match args.archive {
+            true => write_output_with(&args.output, |out| {
+                out.write_all(slot)?;
+                packed::write_file(key, &args.input, size, options, out)
+            }),
+            false if is_std(&args.output) => Err(dir_to_stdout()),
+            false => packed::write_dir(key, &args.input, size, options, slot, &args.output),
+        }
+    } else if args.archive {
+        write_output_with(&args.output, |out| {
+            out.write_all(slot)?;
+            archive::encrypt(key, &args.input, out, options)
+        })
+    } else if is_std(&args.output) {
+        Err(dir_to_stdout())
+    } else {
+        dir::encrypt(key, slot, &args.input, &args.output, options)
+    }
+}
+
+fn dir_to_stdout() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "a directory cannot be written to stdout without '--archive'",
+    )
+}
+
+/// Reads all of the input if it is a file, or the first sealed data of a directory, which tells
+/// what the input is and whether the key is right. With `index_only`, an archive is only read up
+/// to the end of its sealed index. Returns whether the input is a file along with the key slot in
+/// front of what was read, unless it was encrypted by crypt 1.0, and the rest of it.
+fn read_first(path: &Path, index_only: bool) -> io::Result<(bool, Option<Vec<u8>>, Vec<u8>)> {
+    let is_file = is_std(path) || fs::metadata(path)?.is_file();
+    let mut data = match is_file {
+        true if index_only && !is_std(path) => read_archive_index(path)?,
+        true => read_input(path)?,
+        false => fs::read(path.join("00"))?,
+    };
+
+    let (slot, rest) = keyslot::split(&data);
+    // Only crypt 1.0 wrote no key slot, and it never wrote a header.
+    if slot.is_none() && (format::is_sealed(rest) || rest.starts_with(keyslot::MAGIC)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' is missing its key slot, or is corrupted",
+                path.display()
+            ),
+        ));
+    }
+
+    // The slot is taken off the front rather than copying the rest, which may be large.
+    let slot = slot.is_some().then(|| data.drain(..keyslot::LEN).collect());
+    Ok((is_file, slot, data))
+}
+
+/// Reads the file `path` up to the end of the sealed index if it is an archive, reading more and
+/// more of it until the index is all there, or all of it otherwise.
+fn read_archive_index(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let mut data = Vec::new();
+    let mut len = 64 * 1024;
+
+    loop {
+        (&mut file)
+            .take((len - data.len()) as u64)
+            .read_to_end(&mut data)?;
+        let rest = keyslot::split(&data).1;
+
+        if data.len() < len || archive::sealed_index(rest)?.is_some() {
+            return Ok(data);
+        }
+        if !archive::is_archive(rest) {
+            file.read_to_end(&mut data)?;
+            return Ok(data);
+        }
+        len *= 2;
+    }
+}
+
+/// Returns the key `slot` read by `read_first` from `path`, or fails because `path` was encrypted
+/// by crypt 1.0, so it `consequence`.
+fn require_slot(path: &Path, slot: Option<Vec<u8>>, consequence: &str) -> io::Result<Vec<u8>> {
+    slot.ok_or_else(|| older_version(path, consequence))
+}
+
+fn older_version(path: &Path, consequence: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "'{}' was encrypted by an older version of crypt, so it {}",
+            path.display(),
+            consequence
+        ),
+    )
+}
+
+fn decrypt(args: &Args) -> io::Result<()> {
+    let (is_file, slot, first) = read_first(&args.input, false)?;
+
+    // Everything but what crypt 1.0 wrote is encrypted with the key in the key slot.
+    let slot = match slot {
+        Some(slot) => slot,
+        None if matches!(args.mode, Mode::EXTRACT) => {
+            return Err(older_version(
+                &args.input,
+                "can only be decrypted as a whole",
+            ))
+        }
+        None => {
+            eprintln!(
+                "Warning: '{}' was encrypted by an older version of crypt, so a wrong key cannot \
+                 be detected.",
+                args.input.display()
+            );
+            check_overwrite(args, &args.output)?;
+            let key = key::read_key(&args.key_source, &args.mode, false, args.timeout)?;
+
+            return match is_file {
+                true => write_output(&args.output, &legacy::decrypt_file(&key, &first)),
+                false => legacy::decrypt_dir(&key, &args.input, &args.output),
+            };
+        }
+    };
+
+    if is_file && packed::is_packed(&first) {
+        return decrypt_packed(args, &slot, &packed::split(&first)?);
+    }
+    if !is_file && packed::is_blob(&first) {
+        return decrypt_packed(args, &slot, &packed::read_dir(&args.input)?);
+    }
+
+    if archive::is_archive(&first) {
+        if is_std(&args.output) {
+            return Err(decrypted_dir_to_stdout());
+        }
+
+        let sealed = archive::split(&first)?;
+        check_overwrite(args, &args.output)?;
+        let (key, meta) = read_checked_key(args, &slot, sealed[0])?;
+        return archive::decrypt(&key, &meta, &args.patterns, &sealed, &args.output);
+    }
+
+    if !is_file {
+        check_overwrite(args, &args.output)?;
+        let (key, meta) = read_checked_key(args, &slot, &first)?;
+        return dir::decrypt(&key, &meta, &args.patterns, &args.input, &args.output);
+    }
+    if matches!(args.mode, Mode::EXTRACT) {
+        return Err(not_a_dir(&args.input));
+    }
+
+    let (_, plaintext) = read_checked_key(args, &slot, &first)?;
+    let (name, contents) = format::split_name(&first, &plaintext)?;
+
+    let output = match name {
+        Some(name) if args.default_output => original_path(args, name),
+        _ => args.output.clone(),
+    };
+    check_overwrite(args, &output)?;
+    write_output(&output, contents)
+}
+
+/// Returns the path of the file originally named `name` when decrypted next to the input, or the
+/// default output if `name` cannot be used as a file name.
+fn original_path(args: &Args, name: &[u8]) -> PathBuf {
+    match original_name(name) {
+        Some(name) => args.input.with_file_name(name),
+        None => {
+            eprintln!(
+                "Warning: the original name of '{}' cannot be used, decrypting it to '{}'.",
+                args.input.display(),
+                args.output.display()
+            );
+            args.output.clone()
+        }
+    }
+}
+
+/// Fails if removing the input would remove the output along with it.
+fn check_removable(args: &Args) -> io::Result<()> {
+    let input = fs::canonicalize(&args.input)?;
+    let output = &args.output;
+    let parent = match output.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let output = fs::canonicalize(parent)?.join(output.file_name().unwrap_or_default());
+
+    match output.starts_with(&input) {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the output cannot replace the input, or be inside it, with '--remove-source'",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Decrypts the output written from the input, and checks that it holds exactly what the input
+/// does, before the input is removed.
+fn verify_output(args: &Args, key: &[u8]) -> io::Result<()> {
+    let output = &args.output;
+    // Everything is read without the key slot in front of it.
+    let read = |path: &Path| Ok::<_, io::Error>(keyslot::split(&fs::read(path)?).1.to_vec());
+
+    if fs::metadata(&args.input)?.is_file() {
+        let sealed = read(output)?;
+        let plaintext = format::open(key, &sealed)?;
+
+        return match format::split_name(&sealed, &plaintext)?.1 == &fs::read(&args.input)?[..] {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the encrypted '{}' differs from the input",
+                    args.input.display()
+                ),
+            )),
+        };
+    }
+
+    // The archive holding the sealed index and entries, unless they are files in the output.
+    let data = match (args.pack, args.archive) {
+        (Some(_), true) => Some(packed::unpack(key, &packed::split(&read(output)?)?)?),
+        (Some(_), false) => Some(packed::unpack(key, &packed::read_dir(&args.output)?)?),
+        (None, true) => Some(read(output)?),
+        (None, false) => None,
+    };
+    let sealed = data.as_deref().map(archive::split).transpose()?;
+
+    // The index is entry 0, stored as '00'.
+    let read_entry = |n: u32| match &sealed {
+        Some(sealed) => match sealed.get(n as usize) {
+            Some(data) => Ok(data.to_vec()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the encrypted output is missing entries",
+            )),
+        },
+        None => fs::read(output.join(dir::entry_name(n))),
+    };
+    let meta = format::open(key, keyslot::split(&read_entry(0)?).1)?;
+
+    dir::compare(key, &meta, &args.input, args.follow_symlinks, read_entry)
+}
+
+/// Checks that the encrypted `path`, read by `read_first`, can be changed without encrypting it
+/// again, returning the sealed index and entries of an archive, or the sealed index alone.
+fn changeable<'a>(path: &Path, is_file: bool, first: &'a [u8]) -> io::Result<Vec<&'a [u8]>> {
+    if (is_file && packed::is_packed(first)) || (!is_file && packed::is_blob(first)) {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' is packed, so it can only be changed by encrypting it again",
+                path.display()
+            ),
+        ))
+    } else if archive::is_archive(first) {
+        archive::split(first)
+    } else if is_file {
+        Err(not_a_dir(path))
+    } else {
+        Ok(vec![first])
+    }
+}
+
+/// Warns that what is changed in the encrypted `path` is not padded like the rest of it, judging
+/// by its `index`, which decrypts to `meta`.
+fn check_padding(args: &Args, path: &Path, index: &[u8], meta: &[u8]) {
+    if args.padding.is_none() && format::is_padded(index, meta) {
+        eprintln!(
+            "Warning: '{}' is padded, but what is changed is not, use '--pad' to pad it.",
+            path.display()
+        );
+    }
+}
+
+/// Stores `edit` in the encrypted `path`, whose key slot is `slot` and whose sealed data is
+/// `sealed` from `changeable`.
+fn write_edit(
+    args: &Args,
+    key: &[u8],
+    slot: &[u8],
+    edit: edit::Edit,
+    path: &Path,
+    is_file: bool,
+    sealed: &[&[u8]],
+) -> io::Result<()> {
+    match is_file {
+        true => edit::write_archive(key, slot, edit, sealed, args.padding, path),
+        false => edit::write_dir(key, slot, &edit, args.padding, path),
+    }
+}
+
+fn change(args: &Args) -> io::Result<()> {
+    let (is_file, slot, first) = read_first(&args.input, false)?;
+    let slot = require_slot(&args.input, slot, CHANGE_AGAIN)?;
+    let sealed = changeable(&args.input, is_file, &first)?;
+    let (key, meta) = read_checked_key(args, &slot, sealed[0])?;
+    check_padding(args, &args.input, sealed[0], &meta);
+
+    let options = dir::Options {
+        follow_symlinks: args.follow_symlinks,
+        padding: args.padding,
+    };
+    let entries = dir::parse_index(&meta)?;
+    let edit = match args.mode {
+        Mode::REMOVE => edit::remove(entries, &args.patterns)?,
+        Mode::UPDATE => edit::add(&key, entries, &args.source, &args.patterns, true, &options)?,
+        _ => edit::add(&key, entries, &args.source, &args.patterns, false, &options)?,
+    };
+
+    write_edit(args, &key, &slot, edit, &args.input, is_file, &sealed)
+}
+
+fn sync(args: &Args) -> io::Result<()> {
+    if !fs::metadata(&args.input)?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a directory", args.input.display()),
+        ));
+    }
+
+    let options = dir::Options {
+        follow_symlinks: args.follow_symlinks,
+        padding: args.padding,
+    };
+
+    // There is nothing to compare with yet, so everything is encrypted.
+    if fs::symlink_metadata(&args.output).is_err() {
+        let password = key::read_key(
+            &args.key_source,
+            &Mode::ENCRYPT,
+            args.allow_weak_key,
+            args.timeout,
+        )?;
+        let (key, slot) = keyslot::new(&password)?;
+        write_encrypted(args, &key, &slot, &options)?;
+        println!(
+            "Encrypted all of '{}' into '{}'.",
+            args.input.display(),
+            args.output.display()
+        );
+        return Ok(());
+    }
+
+    let (is_file, slot, first) = read_first(&args.output, false)?;
+    let slot = require_slot(&args.output, slot, CHANGE_AGAIN)?;
+    let sealed = changeable(&args.output, is_file, &first)?;
+    let (key, meta) = read_checked_key(args, &slot, sealed[0])?;
+    check_padding(args, &args.output, sealed[0], &meta);
+
+    let (edit, summary) = edit::sync(&key, dir::parse_index(&meta)?, &args.input, &options)?;
+    if let Some(edit) = edit {
+        write_edit(args, &key, &slot, edit, &args.output, is_file, &sealed)?;
+    }
+
+    println!(
+        "Synced '{}' into '{}': {}.",
+        args.input.display(),
+        args.output.display(),
+        summary
     );
+    Ok(())
+}
 
-    match fs::metadata(&input)?.is_file() {
-        true => {
-            let mut contents = fs::read(input)?;
-            match mode {
-                Mode::ENCRYPT => contents = cc.encrypt(&contents),
-                Mode::DECRYPT => contents = cc.decrypt(&contents),
-            }
-            fs::write(output, &contents)?;
+/// Changes the key of the input by sealing the key in its key slot with the new one, leaving
+/// everything else as it is.
+fn passwd(args: &Args) -> io::Result<()> {
+    let (is_file, slot, first) = read_first(&args.input, false)?;
+    let slot = require_slot(
+        &args.input,
+        slot,
+        "can only have its key changed by encrypting it again",
+    )?;
+
+    // What is opened to check the key, like when decrypting.
+    let sealed = if is_file && packed::is_packed(&first) {
+        packed::split(&first)?[0]
+    } else if archive::is_archive(&first) {
+        archive::split(&first)?[0]
+    } else {
+        &first[..]
+    };
+
+    let (key, _) = read_checked_key(args, &slot, sealed)?;
+    let password = key::read_new_key(&args.new_key_source, args.allow_weak_key, args.timeout)?;
+    let data = [keyslot::seal(&password, &key)?, first].concat();
+
+    match is_file {
+        true => atomic::write_file(&args.input, &data),
+        false => atomic::write_file(&args.input.join("00"), &data),
+    }
+}
+
+/// What cannot be done to data encrypted by crypt 1.0 without encrypting it again.
+const CHANGE_AGAIN: &str = "can only be changed by encrypting it again";
+
+fn not_a_dir(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("'{}' is an encrypted file, not a directory", path.display()),
+    )
+}
+
+fn decrypted_dir_to_stdout() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "a directory cannot be decrypted to stdout",
+    )
+}
+
+fn verify(args: &Args) -> io::Result<()> {
+    let (is_file, slot, first) = read_first(&args.input, false)?;
+    let slot = require_slot(&args.input, slot, "cannot be verified")?;
+
+    let problems = if is_file && packed::is_packed(&first) {
+        verify_packed(args, &slot, &packed::split(&first)?)?
+    } else if !is_file && packed::is_blob(&first) {
+        let blobs = packed::read_dir(&args.input)?;
+        let mut problems = verify_packed(args, &slot, &blobs)?;
+        // Blobs are read up to the first missing one, so any after it are extra.
+        problems.extend(verify::extra_files(
+            &args.input,
+            (0..blobs.len() as u32).collect(),
+        )?);
+        problems
+    } else if archive::is_archive(&first) {
+        let sealed = archive::split(&first)?;
+        let (key, meta) = read_checked_key(args, &slot, sealed[0])?;
+        verify::archive(&key, &meta, &sealed)?
+    } else if is_file {
+        // A single file is verified by opening it.
+        read_checked_key(args, &slot, &first)?;
+        Vec::new()
+    } else {
+        let (key, meta) = read_checked_key(args, &slot, &first)?;
+        verify::dir(&key, &meta, &args.input)?
+    };
+
+    for p in &problems {
+        println!("{}", p);
+    }
+
+    match problems.len() {
+        0 => {
+            println!("'{}' is intact.", args.input.display());
             Ok(())
         }
-        false => match mode {
-            Mode::ENCRYPT => {
-                let input = input + "/";
-                let temp_out = format!(".crypt.temp.{}", &output);
-                let mut meta: Vec<(String, String)> = Vec::new();
+        n => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' failed verification with {} problem(s)",
+                args.input.display(),
+                n
+            ),
+        )),
+    }
+}
 
-                match fs::remove_dir_all(&temp_out) {
-                    _ => fs::create_dir(&temp_out)?,
-                }
+fn list(args: &Args) -> io::Result<()> {
+    let (is_file, slot, first) = read_first(&args.input, true)?;
+    let slot = require_slot(&args.input, slot, "cannot be listed")?;
 
-                // Recursively gets all directory contents.
-                fn get_dir_contents(path: &str) -> Result<Vec<String>, io::Error> {
-                    let mut v = Vec::new();
-
-                    for e in fs::read_dir(&path)? {
-                        let path = e?.path();
-                        let path = path.to_str().ok_or(io::Error::new(
-                            io::ErrorKind::NotFound,
-                            "could not read path as UTF-8",
-                        ))?;
-
-                        if fs::metadata(&path)?.is_dir() {
-                            v.append(&mut get_dir_contents(&path)?);
-                        } else {
-                            v.push(path.to_string());
-                        }
-                    }
+    let meta = if is_file && packed::is_packed(&first) {
+        list_packed(args, &slot, &packed::split(&first)?)?
+    } else if !is_file && packed::is_blob(&first) {
+        list_packed(args, &slot, &packed::read_dir(&args.input)?)?
+    } else if archive::is_archive(&first) {
+        read_checked_key(args, &slot, archive::index(&first)?)?.1
+    } else if is_file {
+        return Err(not_a_dir(&args.input));
+    } else {
+        read_checked_key(args, &slot, &first)?.1
+    };
 
-                    Ok(v)
-                }
+    list::print(&dir::parse_index(&meta)?, args.list_format, &mut io::stdout().lock())
+}
 
-                let mut dirs = Vec::new();
+/// Decrypts the index of a directory packed into `blobs`, the first of which checks the key.
+fn list_packed<B: AsRef<[u8]>>(
+    args: &Args,
+    slot: &[u8],
+    blobs: &[B],
+) -> io::Result<Vec<u8>> {
+    let (key, _) = read_checked_key(args, slot, blobs[0].as_ref())?;
+    Ok(format::open(&key, &packed::unpack_index(&key, blobs)?)?)
+}
 
-                for e in get_dir_contents(&input)? {
-                    match e.rfind('/') {
-                        Some(i) => {
-                            if !dirs.contains(&e[0..i].to_string()) {
-                                dirs.push(e[0..i].to_string());
-                            }
-                        }
-                        None => {}
-                    }
+/// Verifies a directory packed into `blobs`, the first of which checks the key.
+fn verify_packed<B: AsRef<[u8]>>(
+    args: &Args,
+    slot: &[u8],
+    blobs: &[B],
+) -> io::Result<Vec<verify::Problem>> {
+    let (key, _) = read_checked_key(args, slot, blobs[0].as_ref())?;
 
-                    meta.push((format!("{:02X}", meta.len() + 1), e.replace(&input, "")));
-                }
-                for d in dirs {
-                    fs::create_dir_all(&d)?;
-                }
+    match verify::blobs(&key, blobs) {
+        (_, Some(packed)) => {
+            let sealed = archive::split(&packed)?;
+            verify::archive(&key, &format::open(&key, sealed[0])?, &sealed)
+        }
+        (problems, None) => Ok(problems),
+    }
+}
 
-                let mut meta_file = String::new();
-                let mut data;
+/// Decrypts a directory packed into `blobs`, the first of which checks the key.
+fn decrypt_packed<B: AsRef<[u8]>>(args: &Args, slot: &[u8], blobs: &[B]) -> io::Result<()> {
+    if is_std(&args.output) {
+        return Err(decrypted_dir_to_stdout());
+    }
 
-                for i in &meta {
-                    meta_file += &format!("{}\n{}\n", i.0, i.1);
-                }
+    check_overwrite(args, &args.output)?;
+    let (key, _) = read_checked_key(args, slot, blobs[0].as_ref())?;
+    packed::decrypt(&key, blobs, &args.patterns, &args.output)
+}
 
-                fs::write(
-                    format!("{}/00", &temp_out),
-                    cc.encrypt(&Vec::from(meta_file.trim())),
-                )?;
+/// Restricts crypt to the files named in `args`, which are the input, the directory the output is
+/// written to, the key file and the terminal.
+fn apply_sandbox(args: &Args) -> io::Result<()> {
+    let mut read = Vec::new();
+    let mut write = vec![PathBuf::from("/dev/tty")];
 
-                for i in &meta {
-                    data = cc.encrypt(&fs::read(format!("{}/{}", &input, i.1))?);
-                    fs::write(format!("{}/{}", &temp_out, i.0), data)?;
-                }
+    if let KeySource::File(path) = &args.key_source {
+        read.push(path.clone());
+    }
+    if !is_std(&args.input) {
+        read.push(args.input.clone());
+    }
+    if let Mode::ADD | Mode::UPDATE = args.mode {
+        read.push(args.source.clone());
+    }
+    if args.remove_source {
+        match args.input.parent() {
+            Some(parent) if parent != Path::new("") => write.push(parent.to_path_buf()),
+            _ => write.push(PathBuf::from(".")),
+        }
+    }
+    if !is_std(&args.output) {
+        match args.output.parent() {
+            Some(parent) if parent != Path::new("") => write.push(parent.to_path_buf()),
+            _ => write.push(PathBuf::from(".")),
+        }
+    }
 
-                match fs::remove_dir_all(&output) {
-                    _ => fs::rename(&temp_out, &output)?,
-                }
+    let read = read.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+    let write = write.iter().map(PathBuf::as_path).collect::<Vec<_>>();
 
-                Ok(())
-            }
-            Mode::DECRYPT => {
-                let temp_out = format!(".crypt.temp.{}", &output);
+    sandbox::apply(&read, &write).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not apply the sandbox ({}), use --no-sandbox to run without it", e),
+        )
+    })
+}
 
-                let meta = String::from_utf8(
-                    cc.decrypt(&fs::read(format!("{}/00", input))?)
-                ).expect("failed to decrypt the meta file");
+/// Returns what interrupted runs of crypt left next to the output.
+fn find_stale(args: &Args) -> Vec<atomic::Stale> {
+    let dir = match args.output.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    // A missing directory is reported when writing the output.
+    atomic::find_stale(dir).unwrap_or_default()
+}
 
-                match fs::remove_dir_all(&temp_out) {
-                    _ => fs::create_dir(&temp_out)?,
-                }
+/// Offers to remove the `stale` files, which may be decrypted data.
+fn remove_stale(args: &Args, stale: Vec<atomic::Stale>) -> io::Result<()> {
+    let mut term = None;
 
-                let meta = meta.split('\n').collect::<Vec<&str>>();
-                let mut data;
-
-                for line in (0..(meta.len())).step_by(2) {
-                    let file = meta[line + 1];
-                    data = cc.decrypt(&fs::read(format!("{}/{}", &input, meta[line]))?);
-
-                    match file.rfind('/') {
-                        Some(i) => {
-                            fs::create_dir_all(format!(
-                                "{}/{}",
-                                &temp_out,
-                                file[0..i].to_string(),
-                            ))?;
-                        }
-                        None => {}
-                    };
-
-                    fs::write(format!("{}/{}", &temp_out, file), data)?;
-                }
+    for s in stale {
+        if let Some(output) = &s.replaced {
+            eprintln!(
+                "Warning: '{}' holds what '{}' was before crypt was interrupted while replacing \
+                 it, rename it back to restore it.",
+                s.path.display(),
+                output.display()
+            );
+            continue;
+        }
 
-                match fs::remove_dir_all(&output) {
-                    _ => fs::rename(&temp_out, &output)?,
+        if term.is_none() {
+            term = Tty::open_with_timeout(args.timeout).ok();
+        }
+        let msg = format!(
+            "'{}' was left behind by an interrupted run of crypt",
+            s.path.display()
+        );
+
+        match &mut term {
+            Some(term) => {
+                if term.confirm(&format!("{}. Remove it?", msg), Some(true))? {
+                    atomic::remove(&s.path)?;
                 }
+            }
+            None => eprintln!("Warning: {}.", msg),
+        }
+    }
+
+    Ok(())
+}
 
-                Ok(())
+/// Reads the key, opens `slot` with it and opens `sealed` with the key the slot holds. A wrong key
+/// typed at the prompt can be retried up to `args.retries` times.
+fn read_checked_key(
+    args: &Args,
+    slot: &[u8],
+    sealed: &[u8],
+) -> io::Result<(Zeroizing<Vec<u8>>, Vec<u8>)> {
+    let (_, opened) = retry_wrong_key(
+        args.retries,
+        matches!(args.key_source, KeySource::Prompt),
+        || key::read_key(&args.key_source, &args.mode, false, args.timeout),
+        |password| {
+            let key = keyslot::open(password, slot)?;
+            Ok((format::open(&key, sealed)?, key))
+        },
+    )?;
+
+    Ok((opened.1, opened.0))
+}
+
+/// Reads a key with `read_key` and opens something with it through `open`. A wrong key is only
+/// read again if it was typed at the `prompt`, up to `retries` times.
+fn retry_wrong_key<K, T>(
+    mut retries: u32,
+    prompt: bool,
+    mut read_key: impl FnMut() -> io::Result<K>,
+    mut open: impl FnMut(&K) -> Result<T, FormatError>,
+) -> io::Result<(K, T)> {
+    loop {
+        let key = read_key()?;
+
+        match open(&key) {
+            Ok(opened) => return Ok((key, opened)),
+            Err(FormatError::Authentication) if retries > 0 && prompt => {
+                retries -= 1;
+                eprintln!("Wrong key, try again.");
             }
+            Err(e) => return Err(e.into()),
         }
     }
 }
 
+/// Returns `name` if it can be used as a file name next to the input.
+fn original_name(name: &[u8]) -> Option<&OsStr> {
+    // '-' would mean stdout.
+    let valid = ![&b""[..], b".", b"..", b"-"].contains(&name)
+        && !name.contains(&b'/')
+        && !name.contains(&b'\0');
+
+    valid.then(|| OsStr::from_bytes(name))
+}
+
+/// Fails if the output `path` already exists, unless `--force` was given or the user agrees to
+/// overwrite it at the prompt. Without a terminal to ask on, it is never overwritten.
+fn check_overwrite(args: &Args, path: &Path) -> io::Result<()> {
+    if is_std(path) || args.force || fs::symlink_metadata(path).is_err() {
+        return Ok(());
+    }
+
+    if let Ok(mut term) = Tty::open_with_timeout(args.timeout) {
+        let prompt = format!("'{}' already exists. Overwrite it?", path.display());
+        if term.confirm(&prompt, Some(false))? {
+            return Ok(());
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "'{}' already exists, use '--force' to overwrite it",
+            path.display()
+        ),
+    ))
+}
+
+/// Reads all of `path`, where `-` stands for `Stdin`.
+fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    if is_std(path) {
+        let mut contents = Vec::new();
+        io::stdin().lock().read_to_end(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Writes the output to `path` with `write`, where `-` stands for `Stdout`.
+fn write_output_with<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    match is_std(path) {
+        true => write(&mut io::stdout().lock()),
+        false => atomic::write_file_with(path, write),
+    }
+}
+
+/// Writes `contents` to `path`, where `-` stands for `Stdout`.
+fn write_output(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if is_std(path) {
+        let mut stdout = io::stdout();
+        stdout.write_all(contents)?;
+        stdout.flush()
+    } else {
+        atomic::write_file(path, contents)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 enum Mode {
     ENCRYPT,
     DECRYPT,
+    EXTRACT,
+    ADD,
+    UPDATE,
+    REMOVE,
+    SYNC,
+    PASSWD,
+    VERIFY,
+    LIST,
+}
+
+struct Args {
+    mode: Mode,
+    input: PathBuf,
+    output: PathBuf,
+    /// Whether `output` was not given, so a decrypted file can be named after its original name.
+    default_output: bool,
+    force: bool,
+    allow_weak_key: bool,
+    key_source: KeySource,
+    /// Where the key replacing the current one is read from, in 'passwd' mode.
+    new_key_source: KeySource,
+    timeout: Option<Duration>,
+    retries: u32,
+    sandbox: bool,
+    archive: bool,
+    follow_symlinks: bool,
+    padding: Option<Padding>,
+    pack: Option<usize>,
+    remove_source: bool,
+    dry_run: bool,
+    list_format: list::Format,
+    /// The patterns of the entries to extract or remove, or the paths to add or update, empty
+    /// unless doing so.
+    patterns: Vec<OsString>,
+    /// Where the paths to add or update are.
+    source: PathBuf,
+}
+
+/// Prints `msg` along with a pointer to the help text, then exits.
+fn usage_error(msg: &str) -> ! {
+    eprintln!("crypt: {}\nTry 'crypt help' for more information.", msg);
+    process::exit(2);
 }
 
-fn argparse() -> (Mode, String, String) {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() < 3 || args.len() > 5 {
+/// Returns where the encrypted `input` is decrypted to by default, which is `input` without its
+/// '.crypt' extension, or `input` itself.
+fn decrypted_path(input: &Path) -> PathBuf {
+    match input.as_os_str().as_bytes().strip_suffix(b".crypt") {
+        Some(stem) if !stem.is_empty() && !stem.ends_with(b"/") => OsStr::from_bytes(stem).into(),
+        _ => input.to_path_buf(),
+    }
+}
+
+/// Returns whether `path` is `-`, which stands for `Stdin` or `Stdout`.
+fn is_std(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Parses the value of an option, which is never valid unless it is UTF-8.
+fn parse_value<T: FromStr>(value: &OsStr) -> Option<T> {
+    value.to_str()?.parse().ok()
+}
+
+/// Parses the arguments `argv`, without the name crypt was run as. Paths are kept as they are
+/// given, whether or not they are UTF-8.
+fn argparse<I: IntoIterator<Item = OsString>>(argv: I) -> Args {
+    let mut allow_weak_key = false;
+    let mut force = false;
+    let mut key_source = KeySource::Prompt;
+    let mut new_key_source = None;
+    let mut timeout = None;
+    let mut retries = 2;
+    let mut sandbox = true;
+    let mut archive = false;
+    let mut follow_symlinks = false;
+    let mut padding = None;
+    let mut pack = None;
+    let mut remove_source = false;
+    let mut dry_run = false;
+    let mut list_format = list::Format::Short;
+    let mut output = None;
+    let mut source = None;
+    let mut args = Vec::new();
+    let mut argv = argv.into_iter();
+
+    while let Some(arg) = argv.next() {
+        let mut value = || {
+            argv.next().unwrap_or_else(|| {
+                usage_error(&format!("'{}' requires a value", arg.to_string_lossy()))
+            })
+        };
+
+        let source = match arg.to_str().unwrap_or_default() {
+            "--allow-weak-key" => {
+                allow_weak_key = true;
+                None
+            }
+            "--key-file" => Some(KeySource::File(value().into())),
+            "--key-env" => Some(KeySource::Env(value())),
+            "--key-fd" => match parse_value(&value()) {
+                Some(fd) => Some(KeySource::Fd(fd)),
+                None => usage_error("'--key-fd' requires a file descriptor number"),
+            },
+            "--passphrase-stdin" => Some(KeySource::Stdin),
+            "--new-key-file" | "--new-key-env" | "--new-key-fd" => {
+                if new_key_source.is_some() {
+                    usage_error("only one source of the new key can be given");
+                }
+                new_key_source = Some(match arg.to_str() {
+                    Some("--new-key-file") => KeySource::File(value().into()),
+                    Some("--new-key-env") => KeySource::Env(value()),
+                    _ => match parse_value(&value()) {
+                        Some(fd) => KeySource::Fd(fd),
+                        None => usage_error("'--new-key-fd' requires a file descriptor number"),
+                    },
+                });
+                None
+            }
+            "--timeout" => match parse_value(&value()) {
+                Some(0) => None,
+                Some(secs) => {
+                    timeout = Some(Duration::from_secs(secs));
+                    None
+                }
+                None => usage_error("'--timeout' requires a number of seconds"),
+            },
+            "--retries" => match parse_value(&value()) {
+                Some(n) => {
+                    retries = n;
+                    None
+                }
+                None => usage_error("'--retries' requires a number"),
+            },
+            "--pad" => {
+                let value = value();
+                padding = match value.to_str() {
+                    Some("padme") => Some(Padding::Padme),
+                    _ => match parse_value(&value) {
+                        Some(0) | None => usage_error(
+                            "'--pad' requires 'padme' or a block size in bytes",
+                        ),
+                        Some(size) => Some(Padding::Block(size)),
+                    },
+                };
+                None
+            }
+            "--pack" => {
+                pack = match parse_value(&value()) {
+                    // The size is stored in 4 bytes in front of packed files.
+                    Some(size) if (packed::MIN_SIZE..=u32::MAX as usize).contains(&size) => {
+                        Some(size)
+                    }
+                    _ => usage_error(&format!(
+                        "'--pack' requires a blob size from {} to {} bytes",
+                        packed::MIN_SIZE,
+                        u32::MAX
+                    )),
+                };
+                None
+            }
+            "--archive" => {
+                archive = true;
+                None
+            }
+            "--output" => {
+                output = Some(PathBuf::from(value()));
+                None
+            }
+            "--source" => {
+                source = Some(PathBuf::from(value()));
+                None
+            }
+            "--force" => {
+                force = true;
+                None
+            }
+            "--follow-symlinks" => {
+                follow_symlinks = true;
+                None
+            }
+            "--long" => {
+                list_format = list::Format::Long;
+                None
+            }
+            "--json" => {
+                list_format = list::Format::Json;
+                None
+            }
+            "--remove-source" => {
+                remove_source = true;
+                None
+            }
+            "--dry-run" => {
+                dry_run = true;
+                None
+            }
+            "--no-sandbox" => {
+                sandbox = false;
+                None
+            }
+            "--help" | "-h" => help(),
+            a if a.starts_with("--") => usage_error(&format!("unknown option '{}'", a)),
+            _ => {
+                args.push(arg);
+                None
+            }
+        };
+
+        if let Some(source) = source {
+            if !matches!(key_source, KeySource::Prompt) {
+                usage_error("only one key source can be given");
+            }
+            key_source = source;
+        }
+    }
+
+    if args.len() < 2 {
         help();
     }
 
-    let mode = match args[1].as_str() {
-        "help" | "h" | "--help" | "-h" => help(),
+    let mode_name = args[0].to_string_lossy().into_owned();
+    let mode = match mode_name.as_str() {
+        "help" | "h" => help(),
         "encrypt" | "enc" | "e" => Mode::ENCRYPT,
         "decrypt" | "dec" | "d" => Mode::DECRYPT,
+        "extract" | "x" => Mode::EXTRACT,
+        "add" | "a" => Mode::ADD,
+        "update" | "u" => Mode::UPDATE,
+        "remove" | "rm" => Mode::REMOVE,
+        "sync" | "s" => Mode::SYNC,
+        "passwd" => Mode::PASSWD,
+        "verify" | "v" => Mode::VERIFY,
+        "list" | "ls" | "l" => Mode::LIST,
         _ => help(),
     };
-    let in_file = args[2].trim_end_matches('/').to_string();
-    let out_file = if args.len() > 3 {
-        args[3].clone()
+    let input = args[1].as_bytes();
+    let end = input.iter().rposition(|&b| b != b'/').map_or(0, |i| i + 1);
+    let input = PathBuf::from(OsStr::from_bytes(&input[..end]));
+
+    let changes = matches!(mode, Mode::ADD | Mode::UPDATE | Mode::REMOVE);
+
+    // Everything after the input is a path when extracting or changing a directory, so the output
+    // is an option.
+    let patterns = match mode {
+        Mode::EXTRACT | Mode::ADD | Mode::UPDATE | Mode::REMOVE => args.split_off(2),
+        _ => Vec::new(),
+    };
+    if args.len() > 3 {
+        help();
+    }
+    if (changes || matches!(mode, Mode::EXTRACT)) && patterns.is_empty() {
+        usage_error(&format!("'{}' requires at least one PATH", mode_name));
+    }
+    if matches!(mode, Mode::PASSWD) && args.len() > 2 {
+        usage_error("'passwd' takes no OUTPUT, the key of INPUT is changed in place");
+    }
+    if new_key_source.is_some() && !matches!(mode, Mode::PASSWD) {
+        usage_error("'--new-key-file', '--new-key-env' and '--new-key-fd' only apply to 'passwd'");
+    }
+    if (changes || matches!(mode, Mode::PASSWD)) && is_std(&input) {
+        usage_error(&format!("'{}' cannot read INPUT from stdin", mode_name));
+    }
+    if matches!(mode, Mode::SYNC) && (args.len() != 3 || is_std(&input) || args[2] == "-") {
+        usage_error("'sync' requires a SRC directory and a DEST to encrypt it into");
+    }
+    if output.is_some() && !matches!(mode, Mode::EXTRACT) {
+        usage_error("'--output' only applies to 'extract'");
+    }
+    if source.is_some() && !matches!(mode, Mode::ADD | Mode::UPDATE) {
+        usage_error("'--source' only applies to 'add' and 'update'");
+    }
+    let default_output = output.is_none() && args.len() == 2 && !is_std(&input);
+
+    let output = if let Some(output) = output {
+        output
+    } else if changes || matches!(mode, Mode::PASSWD) {
+        // The encrypted input is changed in place.
+        input.clone()
+    } else if let Mode::VERIFY | Mode::LIST = mode {
+        if args.len() > 2 {
+            usage_error(&format!("'{}' takes no OUTPUT", mode_name));
+        }
+        // Nothing is written.
+        PathBuf::from("-")
+    } else if args.len() > 2 {
+        PathBuf::from(&args[2])
+    } else if is_std(&input) {
+        input.clone()
     } else {
         match mode {
-            Mode::ENCRYPT => format!("{}.crypt", in_file),
-            Mode::DECRYPT => format!("{}", in_file),
+            Mode::ENCRYPT => {
+                let mut output = input.clone().into_os_string();
+                output.push(".crypt");
+                output.into()
+            }
+            _ => decrypted_path(&input),
         }
     };
 
-    (mode, in_file, out_file)
+    if is_std(&input) && matches!(key_source, KeySource::Stdin) {
+        usage_error("'--passphrase-stdin' cannot be used when the input is read from stdin");
+    }
+    if matches!(mode, Mode::EXTRACT) && is_std(&output) {
+        usage_error("'extract' requires an output directory, see '--output'");
+    }
+    if remove_source && !matches!(mode, Mode::ENCRYPT) {
+        usage_error("'--remove-source' only applies when encrypting");
+    }
+    if remove_source && (is_std(&input) || is_std(&output)) {
+        usage_error("'--remove-source' requires the input and the output to be files");
+    }
+    if !matches!(list_format, list::Format::Short) && !matches!(mode, Mode::LIST) {
+        usage_error("'--long' and '--json' only apply to 'list'");
+    }
+    if pack.is_some() && matches!(mode, Mode::SYNC) {
+        usage_error("'--pack' cannot be used with 'sync', since packed directories cannot change");
+    }
+    if dry_run && !remove_source {
+        usage_error("'--dry-run' only applies to '--remove-source'");
+    }
+
+    Args {
+        mode,
+        input,
+        output,
+        default_output,
+        force,
+        allow_weak_key,
+        key_source,
+        new_key_source: new_key_source.unwrap_or(KeySource::Prompt),
+        timeout,
+        retries,
+        sandbox,
+        archive,
+        follow_symlinks,
+        padding,
+        pack,
+        remove_source,
+        dry_run,
+        list_format,
+        patterns,
+        source: source.unwrap_or_else(|| PathBuf::from(".")),
+    }
 }
@@ -0,0 +1,384 @@
+//! Packing of an encrypted directory into blobs which all have the same size, so that nothing but
+//! its approximate total size can be told from the output.
+//!
+//! The directory is first encrypted into an archive, which is then split into chunks sealed on
+//! their own and padded to the blob size. Every chunk starts with its number, so blobs cannot be
+//! reordered, and whether it is the last one, so they cannot be dropped either:
+//!
+//! ```text
+//! number (4 bytes) | whether it is the last chunk (1 byte) | part of the archive
+//! ```
+//!
+//! The blobs are either stored as files named after their number in hex, like the entries of
+//! `dir`, or one after another in a single file:
+//!
+//! ```text
+//! "CRYPTPK" | blob size (4 bytes) | blobs
+//! ```
+
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use libcrypt::aead::TAG_LEN;
+
+use crate::{archive, atomic, dir, format, keyslot};
+
+pub const MAGIC: &[u8] = b"CRYPTPK";
+/// The smallest blob size, which leaves room for some data after the header of a chunk.
+pub const MIN_SIZE: usize = 64;
+
+pub const CHUNK_HEADER_LEN: usize = 5;
+
+fn corrupted(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns whether `data` is a packed directory stored in a single file.
+pub fn is_packed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Returns whether `sealed` is a blob, as opposed to the index of a directory.
+pub fn is_blob(sealed: &[u8]) -> bool {
+    format::kind(sealed) == Some(format::KIND_BLOB)
+}
+
+/// Splits the archive written into it into chunks, each sealed into a blob once the next one
+/// starts, so that only one chunk is held in memory at a time.
+struct Packer<'a, F> {
+    key: &'a [u8],
+    size: usize,
+    chunk: Vec<u8>,
+    number: u32,
+    emit: F,
+}
+
+impl<F: FnMut(u32, Vec<u8>) -> io::Result<()>> Packer<'_, F> {
+    /// Every chunk needs room for its header and at least one byte of padding.
+    fn chunk_len(&self) -> usize {
+        self.size - CHUNK_HEADER_LEN - 1
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        atomic::check_interrupted()?;
+        let mut plaintext = Vec::with_capacity(self.size);
+        plaintext.extend_from_slice(&self.number.to_le_bytes());
+        plaintext.push(last as u8);
+        plaintext.extend_from_slice(&self.chunk);
+
+        let blob = format::seal_blob(self.key, &plaintext, self.size)?;
+        (self.emit)(self.number, blob)?;
+        self.chunk.clear();
+
+        if !last {
+            self.number = self.number.checked_add(1).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the directory needs too many blobs",
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: FnMut(u32, Vec<u8>) -> io::Result<()>> Write for Packer<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full chunk is only sealed once more data shows that it is not the last one.
+        if self.chunk.len() == self.chunk_len() {
+            self.seal(false)?;
+        }
+
+        let n = buf.len().min(self.chunk_len() - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encrypts the directory `input` into blobs of `size` bytes before encryption, handing each to
+/// `emit` along with its number as soon as it is sealed.
+pub fn encrypt<F: FnMut(u32, Vec<u8>) -> io::Result<()>>(
+    key: &[u8],
+    input: &Path,
+    size: usize,
+    options: &dir::Options,
+    emit: F,
+) -> io::Result<()> {
+    let mut packer = Packer {
+        key,
+        size,
+        chunk: Vec::with_capacity(size),
+        number: 0,
+        emit,
+    };
+
+    archive::encrypt(key, input, &mut packer, options)?;
+    packer.seal(true)
+}
+
+/// Encrypts the directory `input` into blobs of `size` bytes before encryption, written as files
+/// in the directory `output` with the key `slot` in front of the first.
+pub fn write_dir(
+    key: &[u8],
+    input: &Path,
+    size: usize,
+    options: &dir::Options,
+    slot: &[u8],
+    output: &Path,
+) -> io::Result<()> {
+    let temp = atomic::Temp::dir(output)?;
+
+    encrypt(key, input, size, options, |i, blob| {
+        let path = temp.path().join(dir::entry_name(i));
+        match i {
+            0 => atomic::write(&path, &[slot, &blob].concat()),
+            _ => atomic::write(&path, &blob),
+        }
+    })?;
+
+    temp.commit()
+}
+
+/// Encrypts the directory `input` into blobs of `size` bytes before encryption, written one after
+/// another into a single file.
+pub fn write_file<W: Write + ?Sized>(
+    key: &[u8],
+    input: &Path,
+    size: usize,
+    options: &dir::Options,
+    out: &mut W,
+) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&(size as u32).to_le_bytes())?;
+
+    encrypt(key, input, size, options, |_, blob| out.write_all(&blob))?;
+    out.flush()
+}
+
+/// Reads the blobs stored as files in the directory `input`, leaving out the key slot in front of
+/// the first.
+pub fn read_dir(input: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let mut blobs = Vec::new();
+
+    loop {
+        match fs::read(input.join(dir::entry_name(blobs.len() as u32))) {
+            Ok(blob) if blobs.is_empty() => blobs.push(keyslot::split(&blob).1.to_vec()),
+            Ok(blob) => blobs.push(blob),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(blobs),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Splits a single file written by `write_file` into its blobs.
+pub fn split(data: &[u8]) -> io::Result<Vec<&[u8]>> {
+    let header_len = MAGIC.len() + 4;
+    if !is_packed(data) || data.len() < header_len {
+        return Err(corrupted("the packed file is corrupted"));
+    }
+
+    let size = u32::from_le_bytes(data[MAGIC.len()..header_len].try_into().unwrap()) as usize;
+    let sealed_len = format::HEADER_LEN + size + TAG_LEN;
+    let blobs = &data[header_len..];
+
+    if size < MIN_SIZE || blobs.is_empty() || !blobs.len().is_multiple_of(sealed_len) {
+        return Err(corrupted("the packed file is corrupted"));
+    }
+
+    Ok(blobs.chunks(sealed_len).collect())
+}
+
+/// Opens blob number `i`, returning its chunk.
+fn open_chunk(key: &[u8], blob: &[u8], i: usize) -> io::Result<Vec<u8>> {
+    if !is_blob(blob) {
+        return Err(corrupted("a blob is corrupted"));
+    }
+
+    let chunk = format::open(key, blob)?;
+    if chunk.len() < CHUNK_HEADER_LEN {
+        return Err(corrupted("a blob is corrupted"));
+    }
+    if u32::from_le_bytes(chunk[..4].try_into().unwrap()) as usize != i {
+        return Err(corrupted("the blobs are out of order"));
+    }
+
+    Ok(chunk)
+}
+
+/// Opens every blob and joins their chunks back into the archive.
+pub fn unpack<B: AsRef<[u8]>>(key: &[u8], blobs: &[B]) -> io::Result<Vec<u8>> {
+    let mut packed = Vec::new();
+
+    for (i, blob) in blobs.iter().enumerate() {
+        let chunk = open_chunk(key, blob.as_ref(), i)?;
+        packed.extend_from_slice(&chunk[CHUNK_HEADER_LEN..]);
+
+        if chunk[4] == 1 {
+            return match i + 1 == blobs.len() {
+                true => Ok(packed),
+                false => Err(corrupted("there are blobs after the last one")),
+            };
+        }
+    }
+
+    Err(corrupted("the last blobs are missing"))
+}
+
+/// Opens blobs only until the sealed index at the start of the archive is complete, and returns
+/// it, so the contents of the directory can be listed without decrypting all of it.
+pub fn unpack_index<B: AsRef<[u8]>>(key: &[u8], blobs: &[B]) -> io::Result<Vec<u8>> {
+    let mut packed = Vec::new();
+
+    for (i, blob) in blobs.iter().enumerate() {
+        let chunk = open_chunk(key, blob.as_ref(), i)?;
+        packed.extend_from_slice(&chunk[CHUNK_HEADER_LEN..]);
+
+        if let Some(index) = archive::sealed_index(&packed)? {
+            return Ok(index.to_vec());
+        }
+        if chunk[4] == 1 {
+            break;
+        }
+    }
+
+    Err(corrupted("the last blobs are missing"))
+}
+
+/// Decrypts the directory packed into `blobs` into `output`, or only the entries matching
+/// `patterns` if any are given.
+pub fn decrypt<B: AsRef<[u8]>>(
+    key: &[u8],
+    blobs: &[B],
+    patterns: &[OsString],
+    output: &Path,
+) -> io::Result<()> {
+    let packed = unpack(key, blobs)?;
+    let sealed = archive::split(&packed)?;
+    let meta = format::open(key, sealed[0])?;
+
+    archive::decrypt(key, &meta, patterns, &sealed, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{self, TempDir, KEY};
+
+    const OPTIONS: dir::Options = dir::Options {
+        follow_symlinks: false,
+        padding: None,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new("packed-round-trip");
+        let src = test::source_tree(&dir);
+        let blobs = test::packed_blobs(&src);
+        assert!(blobs.len() > 1);
+        assert!(blobs
+            .iter()
+            .all(|b| is_blob(b) && b.len() == blobs[0].len()));
+        decrypt(&KEY, &blobs, &[], &dir.join("out")).unwrap();
+        assert_eq!(test::tree(&dir.join("out")), test::tree(&src));
+
+        // Only the size of a key slot matters to `read_dir`, which leaves it out.
+        let slot = [keyslot::MAGIC, &[0; keyslot::LEN][keyslot::MAGIC.len()..]].concat();
+        let enc = dir.join("enc");
+        write_dir(&KEY, &src, MIN_SIZE, &OPTIONS, &slot, &enc).unwrap();
+        assert!(fs::read(enc.join("00")).unwrap().starts_with(&slot));
+        let read = read_dir(&enc).unwrap();
+        assert_eq!(read.len(), blobs.len());
+        decrypt(&KEY, &read, &[], &dir.join("out-dir")).unwrap();
+        assert_eq!(test::tree(&dir.join("out-dir")), test::tree(&src));
+
+        let mut data = Vec::new();
+        write_file(&KEY, &src, MIN_SIZE, &OPTIONS, &mut data).unwrap();
+        assert!(is_packed(&data));
+        let split = split(&data).unwrap();
+        assert_eq!(split.len(), blobs.len());
+        decrypt(&KEY, &split, &[], &dir.join("out-file")).unwrap();
+        assert_eq!(test::tree(&dir.join("out-file")), test::tree(&src));
+
+        // The index alone needs only the first blobs.
+        let packed = unpack(&KEY, &blobs).unwrap();
+        let index = archive::index(&packed).unwrap();
+        assert_eq!(unpack_index(&KEY, &blobs).unwrap(), index);
+        assert!(!is_blob(index));
+    }
+
+    #[test]
+    fn test_chunk_boundary() {
+        for len in [1, 57, 58, 59, 116, 117] {
+            let mut blobs = Vec::new();
+            let mut packer = Packer {
+                key: &KEY,
+                size: MIN_SIZE,
+                chunk: Vec::new(),
+                number: 0,
+                emit: |_, blob| {
+                    blobs.push(blob);
+                    Ok(())
+                },
+            };
+            packer.write_all(&vec![1; len]).unwrap();
+            packer.seal(true).unwrap();
+
+            // A full chunk at the end is still the last one.
+            assert_eq!(blobs.len(), len.div_ceil(MIN_SIZE - CHUNK_HEADER_LEN - 1));
+            assert_eq!(unpack(&KEY, &blobs).unwrap(), vec![1; len]);
+        }
+    }
+
+    #[test]
+    fn test_rejected() {
+        let dir = TempDir::new("packed-rejected");
+        let blobs = test::packed_blobs(&test::source_tree(&dir));
+        let n = blobs.len();
+
+        let mut swapped = blobs.clone();
+        swapped.swap(0, 1);
+        assert!(unpack(&KEY, &swapped).is_err());
+        assert!(unpack(&KEY, &blobs[..n - 1]).is_err());
+        assert!(unpack(&KEY, &[&blobs[..], &blobs[n - 1..]].concat()).is_err());
+
+        let mut tampered = blobs.clone();
+        tampered[1][format::HEADER_LEN] ^= 1;
+        assert!(unpack(&KEY, &tampered).is_err());
+        assert!(unpack(&[8; 32], &blobs).is_err());
+    }
+
+    #[test]
+    fn test_split_truncated() {
+        let dir = TempDir::new("packed-truncated");
+        let src = test::source_tree(&dir);
+        let mut data = Vec::new();
+        write_file(&KEY, &src, MIN_SIZE, &OPTIONS, &mut data).unwrap();
+
+        // Blobs cut off at a boundary are only missed once unpacked.
+        let header_len = MAGIC.len() + 4;
+        let sealed_len = format::HEADER_LEN + MIN_SIZE + TAG_LEN;
+        for len in 0..data.len() {
+            match len.checked_sub(header_len).map(|l| l % sealed_len) {
+                Some(0) if len > header_len => {
+                    assert!(unpack(&KEY, &split(&data[..len]).unwrap()).is_err())
+                }
+                _ => assert!(split(&data[..len]).is_err(), "{}", len),
+            }
+        }
+
+        // A blob size below the smallest one.
+        let mut small = data.clone();
+        small[MAGIC.len()] = 1;
+        assert!(split(&small).is_err());
+    }
+}
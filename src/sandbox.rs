@@ -0,0 +1,417 @@
+//! Sandboxing of the crypt process once the arguments are parsed, so a bug cannot touch anything
+//! but the files crypt was asked to work on.
+//!
+//! Landlock limits the filesystem to the given paths, and a seccomp filter limits the process to
+//! the system calls crypt needs to read and write files. Neither lets go of a restriction once it
+//! is applied. Kernels without Landlock only get the seccomp filter.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// Landlock has no wrappers in libc, but its system calls have the same numbers everywhere.
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+const ACCESS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_MAKE_SYM: u64 = 1 << 12;
+const ACCESS_REFER: u64 = 1 << 13;
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+
+/// Everything the first version of Landlock can restrict.
+const ACCESS_ABI_1: u64 = (1 << 13) - 1;
+/// The rights which can be given on a file, rather than a directory.
+const ACCESS_FILE: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE;
+
+const ACCESS_READ: u64 = ACCESS_READ_FILE | ACCESS_READ_DIR;
+const ACCESS_WRITE: u64 = ACCESS_READ
+    | ACCESS_WRITE_FILE
+    | ACCESS_REMOVE_DIR
+    | ACCESS_REMOVE_FILE
+    | ACCESS_MAKE_DIR
+    | ACCESS_MAKE_REG
+    | ACCESS_MAKE_SYM
+    | ACCESS_REFER
+    | ACCESS_TRUNCATE;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Restricts the process to reading the paths in `read` and to changing anything beneath the
+/// paths in `write`, as well as to the system calls in `ALLOWED_SYSCALLS` and `allowed_args`. Paths which do not
+/// exist are skipped, as is anything the kernel does not support.
+pub fn apply(read: &[&Path], write: &[&Path]) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    landlock(read, write)?;
+    seccomp()
+}
+
+fn landlock(read: &[&Path], write: &[&Path]) -> io::Result<()> {
+    let abi = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        // Landlock is not built into the kernel, or is disabled.
+        return Ok(());
+    }
+
+    let mut handled = ACCESS_ABI_1;
+    if abi >= 2 {
+        handled |= ACCESS_REFER;
+    }
+    if abi >= 3 {
+        handled |= ACCESS_TRUNCATE;
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            &attr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = ruleset as libc::c_int;
+
+    let res = read
+        .iter()
+        .map(|p| (p, ACCESS_READ))
+        .chain(write.iter().map(|p| (p, ACCESS_WRITE)))
+        .try_for_each(|(path, access)| add_rule(ruleset, path, access & handled))
+        .and_then(
+            |_| match unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset, 0) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            },
+        );
+
+    unsafe { libc::close(ruleset) };
+    res
+}
+
+fn add_rule(ruleset: libc::c_int, path: &Path, mut access: u64) -> io::Result<()> {
+    let cpath = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let fd = unsafe { libc::open(cpath.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return match io::Error::last_os_error().kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        };
+    }
+
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } == 0 && stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        access &= ACCESS_FILE;
+    }
+
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd,
+    };
+    let res = unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_ADD_RULE,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr,
+            0,
+        )
+    };
+    let res = match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    };
+
+    unsafe { libc::close(fd) };
+    res
+}
+
+/// The system calls crypt makes after the sandbox is applied. Anything else fails with `EPERM`.
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    // Memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_mlock,
+    libc::SYS_munlock,
+    libc::SYS_futex,
+    // Files
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_pwrite64,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_getdents64,
+    libc::SYS_fcntl,
+    libc::SYS_ftruncate,
+    libc::SYS_copy_file_range,
+    libc::SYS_sendfile,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_mkdirat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_readlinkat,
+    libc::SYS_symlinkat,
+    libc::SYS_umask,
+    // Landlock does not restrict changing the permissions or owner of a file, so that is only
+    // allowed through a descriptor of a file Landlock let crypt open. `utimensat` can still change
+    // the times of any file by path, as it is also how `futimens` sets them through a descriptor.
+    libc::SYS_fchmod,
+    libc::SYS_fchown,
+    libc::SYS_utimensat,
+    libc::SYS_faccessat,
+    libc::SYS_getcwd,
+    // Terminal, with `ioctl` in `ALLOWED_ARGS`
+    libc::SYS_ppoll,
+    // Signals, which are re-raised after restoring the terminal
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    // Everything else
+    libc::SYS_getrandom,
+    libc::SYS_clock_gettime,
+    libc::SYS_sched_yield,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mkdir,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rmdir,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_symlink,
+];
+
+/// The system calls crypt makes only with some values of an argument, as the index of the
+/// argument and the values.
+fn allowed_args() -> Vec<(libc::c_long, u32, Vec<u32>)> {
+    let pid = std::process::id();
+
+    vec![
+        // Reading and setting the terminal attributes, but not pushing input into the terminal.
+        (
+            libc::SYS_ioctl,
+            1,
+            vec![
+                libc::TCGETS as u32,
+                libc::TCSETS as u32,
+                libc::TCSETSW as u32,
+                libc::TCSETSF as u32,
+                libc::TIOCGWINSZ as u32,
+            ],
+        ),
+        // Signals are only re-raised at crypt itself.
+        (libc::SYS_kill, 0, vec![pid]),
+        (libc::SYS_tgkill, 0, vec![pid]),
+    ]
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// The classic BPF instructions seccomp filters are made of.
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_RET_K: u16 = 0x06;
+
+// Offsets into `struct seccomp_data`. The arguments are 64 bits each, and only their low half is
+// compared, which comes first on the little-endian architectures the filter is built for. The
+// arguments checked are all `int` or `unsigned int`, so the kernel ignores the high half.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARGS: u32 = 16;
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: BPF_JMP_JEQ_K,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn seccomp() -> io::Result<()> {
+    let deny = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+
+    // System calls of another architecture have other numbers, so they are refused outright. The
+    // instructions marked `true` jump to the final `ALLOW` when they match.
+    let mut checks = vec![
+        (stmt(BPF_LD_W_ABS, DATA_ARCH), false),
+        (jump(AUDIT_ARCH, 1, 0), false),
+        (stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS), false),
+        (stmt(BPF_LD_W_ABS, DATA_NR), false),
+    ];
+    for nr in ALLOWED_SYSCALLS {
+        checks.push((jump(*nr as u32, 0, 0), true));
+    }
+    for (nr, arg, values) in allowed_args() {
+        // Skips the argument checks, leaving the number loaded, for other system calls.
+        checks.push((jump(nr as u32, 0, values.len() as u8 + 2), false));
+        checks.push((stmt(BPF_LD_W_ABS, DATA_ARGS + 8 * arg), false));
+        for value in values {
+            checks.push((jump(value, 0, 0), true));
+        }
+        checks.push((stmt(BPF_RET_K, deny), false));
+    }
+    checks.push((stmt(BPF_RET_K, deny), false));
+
+    let allow = checks.len();
+    let mut filter = checks
+        .into_iter()
+        .enumerate()
+        .map(|(i, (mut insn, to_allow))| {
+            if to_allow {
+                insn.jt = (allow - i - 1) as u8;
+            }
+            insn
+        })
+        .collect::<Vec<_>>();
+    filter.push(stmt(BPF_RET_K, libc::SECCOMP_RET_ALLOW));
+
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+
+    match unsafe { libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &prog) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn seccomp() -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+
+    fn errno(res: libc::c_int) -> Option<i32> {
+        match res {
+            -1 => io::Error::last_os_error().raw_os_error(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_forbidden() {
+        let dir = TempDir::new("sandbox-forbidden");
+        let path = dir.path().to_path_buf();
+
+        // The sandbox only applies to the thread which applied it.
+        thread::spawn(move || {
+            apply(&[], &[&path]).unwrap();
+            let pid = std::process::id() as libc::pid_t;
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+
+            unsafe {
+                let tiocsti = libc::ioctl(0, libc::TIOCSTI, b"a".as_ptr());
+                assert_eq!(errno(tiocsti), Some(libc::EPERM));
+                let tcgets = libc::ioctl(0, libc::TCGETS, &mut termios);
+                assert_ne!(errno(tcgets), Some(libc::EPERM));
+
+                assert_eq!(errno(libc::kill(1, 0)), Some(libc::EPERM));
+                assert_eq!(errno(libc::kill(pid, 0)), None);
+
+                let socket = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+                assert_eq!(errno(socket), Some(libc::EPERM));
+            }
+
+            let file = path.join("a");
+            fs::write(&file, "a").unwrap();
+            let perms = fs::Permissions::from_mode(0o600);
+            let err = fs::set_permissions(&file, perms.clone()).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+            fs::File::open(&file)
+                .unwrap()
+                .set_permissions(perms)
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Removal of the source of an encrypted output, overwriting its files with random data first so
+//! that their contents cannot be read back from the disk.
+//!
+//! Overwriting only helps where a file is rewritten in place. Copy-on-write and journaling
+//! filesystems, as well as SSDs, may keep the old contents elsewhere.
+
+use std::cmp;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use libcrypt::random;
+
+/// How something in the source is removed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Overwritten with random data, then removed.
+    Shred,
+    /// Removed, which is how symlinks go, since what they lead to is not part of the source.
+    Unlink,
+    /// Removed once everything in it is.
+    RemoveDir,
+}
+
+impl Action {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Action::Shred => "overwrite and remove",
+            Action::Unlink => "remove the symlink",
+            Action::RemoveDir => "remove the directory",
+        }
+    }
+}
+
+fn plan_dir(dir: &Path, plan: &mut Vec<(PathBuf, Action)>) -> io::Result<()> {
+    let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|e| e.file_name());
+
+    for e in children {
+        let path = e.path();
+        let file_type = e.file_type()?;
+
+        if file_type.is_dir() {
+            plan_dir(&path, plan)?;
+        } else if file_type.is_symlink() {
+            plan.push((path, Action::Unlink));
+        } else if file_type.is_file() {
+            plan.push((path, Action::Shred));
+        }
+        // Anything else was not encrypted, so it stays, and so does its directory.
+    }
+
+    plan.push((dir.to_path_buf(), Action::RemoveDir));
+    Ok(())
+}
+
+/// Lists what removing the file or directory `input` takes, in the order it is done. Symlinks are
+/// never followed.
+pub fn plan(input: &Path) -> io::Result<Vec<(PathBuf, Action)>> {
+    let mut plan = Vec::new();
+    let meta = fs::symlink_metadata(input)?;
+
+    if meta.is_dir() {
+        plan_dir(input, &mut plan)?;
+    } else if meta.file_type().is_symlink() {
+        plan.push((input.to_path_buf(), Action::Unlink));
+    } else {
+        plan.push((input.to_path_buf(), Action::Shred));
+    }
+
+    Ok(plan)
+}
+
+/// Overwrites the file at `path` with random data, syncs and truncates it, then removes it.
+fn shred(path: &Path) -> io::Result<()> {
+    let open = || {
+        OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)
+    };
+
+    let mut file = match open() {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            // Through a descriptor, as the sandbox refuses changing permissions by path.
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(path)?
+                .set_permissions(fs::Permissions::from_mode(0o600))?;
+            open()?
+        }
+        res => res?,
+    };
+
+    let meta = file.metadata()?;
+    if meta.nlink() > 1 {
+        eprintln!(
+            "Warning: '{}' has other hard links, so it is removed without being overwritten.",
+            path.display()
+        );
+        return fs::remove_file(path);
+    }
+
+    let mut buf = vec![0; 64 * 1024];
+    let mut left = meta.len();
+
+    while left > 0 {
+        let n = cmp::min(left, buf.len() as u64) as usize;
+        random::fill(&mut buf[..n])?;
+        file.write_all(&buf[..n])?;
+        left -= n as u64;
+    }
+
+    file.sync_all()?;
+    file.set_len(0)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::remove_file(path)
+}
+
+/// Carries out `plan`. Directories which still hold something that was not encrypted are kept.
+pub fn remove(plan: &[(PathBuf, Action)]) -> io::Result<()> {
+    for (path, action) in plan {
+        match action {
+            Action::Shred => shred(path)?,
+            Action::Unlink => fs::remove_file(path)?,
+            Action::RemoveDir => match fs::remove_dir(path) {
+                Err(e) if e.raw_os_error() == Some(libc::ENOTEMPTY) => eprintln!(
+                    "Warning: keeping '{}', which holds something that was not encrypted.",
+                    path.display()
+                ),
+                res => res?,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{self, TempDir};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    fn described(plan: &[(PathBuf, Action)], root: &Path) -> Vec<(String, &'static str)> {
+        plan.iter()
+            .map(|(p, a)| {
+                let p = p.strip_prefix(root).unwrap();
+                (p.display().to_string(), a.describe())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let dir = TempDir::new("shred-plan");
+        let src = test::source_tree(&dir);
+
+        assert_eq!(
+            described(&plan(&src).unwrap(), &src),
+            [
+                ("a", Action::Shred),
+                ("l", Action::Unlink),
+                ("sub/b", Action::Shred),
+                ("sub/empty", Action::RemoveDir),
+                ("sub", Action::RemoveDir),
+                ("", Action::RemoveDir),
+            ]
+            .iter()
+            .map(|(p, a)| (p.to_string(), a.describe()))
+            .collect::<Vec<_>>()
+        );
+
+        let l = src.join("l");
+        assert_eq!(
+            described(&plan(&l).unwrap(), &l),
+            [(String::new(), "remove the symlink")]
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let dir = TempDir::new("shred-remove");
+        let src = test::source_tree(&dir);
+        let outside = dir.join("outside");
+        fs::write(&outside, b"kept").unwrap();
+        symlink(&outside, src.join("sub/out")).unwrap();
+        fs::set_permissions(src.join("a"), fs::Permissions::from_mode(0o400)).unwrap();
+
+        remove(&plan(&src).unwrap()).unwrap();
+        assert!(fs::symlink_metadata(&src).is_err());
+        assert_eq!(fs::read(&outside).unwrap(), b"kept");
+    }
+
+    #[test]
+    fn test_remove_keeps() {
+        let dir = TempDir::new("shred-keeps");
+        let src = test::source_tree(&dir);
+        let fifo = std::ffi::CString::new(src.join("sub/fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        // The contents of a file with another hard link stay readable through it.
+        let link = dir.join("link");
+        fs::hard_link(src.join("a"), &link).unwrap();
+
+        remove(&plan(&src).unwrap()).unwrap();
+        assert_eq!(fs::read(&link).unwrap(), b"alpha");
+        // Reading the fifo would block, so only what is left is listed.
+        let left = |dir: &Path| {
+            let mut names = fs::read_dir(dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(left(&src), ["sub"]);
+        assert_eq!(left(&src.join("sub")), ["fifo"]);
+    }
+}
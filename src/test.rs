@@ -1,8 +1,98 @@
 #![cfg(test)]
-mod tests;
+#![allow(clippy::assertions_on_constants)]
 
 use super::*;
 
+/// A scratch directory for a test, removed along with everything in it when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("crypt-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A key to seal test data with.
+pub const KEY: [u8; 32] = [7; 32];
+
+/// Fills `dir/src` with a file, a directory holding another file and an empty directory, and a
+/// symlink, returning its path.
+pub fn source_tree(dir: &TempDir) -> PathBuf {
+    let src = dir.join("src");
+    fs::create_dir_all(src.join("sub/empty")).unwrap();
+    fs::write(src.join("a"), b"alpha").unwrap();
+    fs::write(src.join("sub/b"), b"beta").unwrap();
+    std::os::unix::fs::symlink("a", src.join("l")).unwrap();
+    src
+}
+
+/// Packs the tree at `src` into blobs of the smallest size, numbered by their position.
+pub fn packed_blobs(src: &Path) -> Vec<Vec<u8>> {
+    let options = dir::Options {
+        follow_symlinks: false,
+        padding: None,
+    };
+    let mut blobs = Vec::new();
+    packed::encrypt(&KEY, src, packed::MIN_SIZE, &options, |i, blob| {
+        assert_eq!(i as usize, blobs.len());
+        blobs.push(blob);
+        Ok(())
+    })
+    .unwrap();
+    blobs
+}
+
+/// Lists everything in the tree at `root` with the contents of files and the targets of symlinks,
+/// to compare trees with.
+pub fn tree(root: &Path) -> Vec<(PathBuf, String)> {
+    fn walk(root: &Path, rel: &Path, out: &mut Vec<(PathBuf, String)>) {
+        let path = root.join(rel);
+        let meta = fs::symlink_metadata(&path).unwrap();
+
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(&path).unwrap();
+            out.push((rel.to_path_buf(), format!("-> {}", target.display())));
+        } else if meta.is_dir() {
+            out.push((rel.to_path_buf(), String::from("/")));
+            let mut names = fs::read_dir(&path)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                walk(root, &rel.join(name), out);
+            }
+        } else {
+            let contents = fs::read(&path).unwrap();
+            out.push((
+                rel.to_path_buf(),
+                String::from_utf8_lossy(&contents).into_owned(),
+            ));
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(root, Path::new(""), &mut out);
+    out
+}
+
 #[test]
 fn it_works() {
     assert!(1 + 1 == 2);
@@ -12,3 +102,139 @@ fn it_works() {
 fn enc_dec() {
     assert!(true);
 }
+
+/// Runs `retry_wrong_key` with the keys `typed` in turn, of which only `right` opens anything,
+/// returning the result along with how many keys were read.
+fn retried(typed: &[&str], right: &str, retries: u32, prompt: bool) -> (io::Result<String>, usize) {
+    let mut read = 0;
+    let res = retry_wrong_key(
+        retries,
+        prompt,
+        || {
+            read += 1;
+            typed
+                .get(read - 1)
+                .map(|k| k.to_string())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no more keys"))
+        },
+        |key| match key == right {
+            true => Ok(()),
+            false => Err(FormatError::Authentication),
+        },
+    );
+
+    (res.map(|(key, _)| key), read)
+}
+
+#[test]
+fn test_retry_wrong_key() {
+    let (res, read) = retried(&["a", "b", "right"], "right", 2, true);
+    assert_eq!((res.unwrap(), read), (String::from("right"), 3));
+
+    // Out of retries.
+    let (res, read) = retried(&["a", "b", "c", "right"], "right", 2, true);
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(read, 3);
+
+    // Keys which were not typed are not read again.
+    let (res, read) = retried(&["a", "right"], "right", 2, false);
+    assert!(res.is_err());
+    assert_eq!(read, 1);
+
+    let (res, read) = retried(&["right"], "right", 0, true);
+    assert_eq!((res.unwrap(), read), (String::from("right"), 1));
+}
+
+#[test]
+fn test_retry_other_errors() {
+    // Data from a newer version is not retried, nor is a failure to read the key.
+    let res = retry_wrong_key(
+        2,
+        true,
+        || Ok(()),
+        |_| -> Result<(), _> { Err(FormatError::UnsupportedVersion(9)) },
+    );
+    assert!(res.is_err());
+
+    let (res, read) = retried(&[], "right", 2, true);
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(read, 1);
+}
+
+/// Parses `argv`, given as bytes so that they need not be UTF-8.
+fn parsed(argv: &[&[u8]]) -> Args {
+    argparse(argv.iter().map(|a| OsStr::from_bytes(a).to_os_string()))
+}
+
+fn bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+#[test]
+fn test_argparse() {
+    let args = parsed(&[b"enc", b"dir//"]);
+    assert!(matches!(args.mode, Mode::ENCRYPT));
+    assert_eq!(args.input, Path::new("dir"));
+    assert_eq!(args.output, Path::new("dir.crypt"));
+    assert!(args.default_output);
+
+    let args = parsed(&[b"d", b"-", b"--timeout", b"5", b"--key-file", b"k", b"out"]);
+    assert!(matches!(args.mode, Mode::DECRYPT));
+    assert!(is_std(&args.input));
+    assert_eq!(args.output, Path::new("out"));
+    assert!(!args.default_output);
+    assert_eq!(args.timeout, Some(Duration::from_secs(5)));
+    assert!(matches!(args.key_source, KeySource::File(path) if path == Path::new("k")));
+
+    let args = parsed(&[b"add", b"enc", b"--source", b"src", b"a", b"b/c"]);
+    assert_eq!(args.output, Path::new("enc"));
+    assert_eq!(args.source, Path::new("src"));
+    assert_eq!(args.patterns, [OsString::from("a"), OsString::from("b/c")]);
+}
+
+#[test]
+fn test_argparse_not_utf8() {
+    let args = parsed(&[b"decrypt", b"\xff.crypt"]);
+    assert_eq!(bytes(&args.input), b"\xff.crypt");
+    assert_eq!(bytes(&args.output), b"\xff");
+
+    let args = parsed(&[b"e", b"\xfe", b"--key-file", b"k\xff"]);
+    assert_eq!(bytes(&args.output), b"\xfe.crypt");
+    assert!(matches!(args.key_source, KeySource::File(path) if bytes(&path) == b"k\xff"));
+
+    let args = parsed(&[b"x", b"in", b"--output", b"out\xfe", b"a\xff", b"*"]);
+    assert_eq!(bytes(&args.output), b"out\xfe");
+    assert_eq!(args.patterns[0].as_bytes(), b"a\xff");
+}
+
+#[test]
+fn test_decrypted_path() {
+    let decrypted = |input: &str| decrypted_path(Path::new(input));
+
+    assert_eq!(decrypted("foo.txt.crypt"), Path::new("foo.txt"));
+    assert_eq!(decrypted("dir/foo.crypt"), Path::new("dir/foo"));
+    assert_eq!(decrypted("foo.crypt.txt"), Path::new("foo.crypt.txt"));
+    assert_eq!(decrypted(".crypt"), Path::new(".crypt"));
+    assert_eq!(decrypted("dir/.crypt"), Path::new("dir/.crypt"));
+    assert_eq!(decrypted("secret_msg"), Path::new("secret_msg"));
+}
+
+#[test]
+fn test_original_name() {
+    assert_eq!(original_name(b"foo.txt"), Some(OsStr::new("foo.txt")));
+    assert_eq!(original_name(b".hidden"), Some(OsStr::new(".hidden")));
+    assert_eq!(original_name(b"\xff"), Some(OsStr::from_bytes(b"\xff")));
+    for name in [
+        &b""[..],
+        b".",
+        b"..",
+        b"-",
+        b"dir/foo",
+        b"/etc/passwd",
+        b"a\0b",
+    ]
+    .iter()
+    {
+        assert_eq!(original_name(name), None);
+    }
+}
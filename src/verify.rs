@@ -0,0 +1,300 @@
+//! Verification of encrypted directories without decrypting them to disk. Every entry is
+//! authenticated and checked to be the one the index lists under its number, and anything the
+//! index does not list is reported.
+
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+use crate::{dir, format, packed};
+
+/// Something wrong with an encrypted directory, naming the entries or blobs involved.
+pub enum Problem {
+    /// Does not authenticate, so it was modified or belongs to something else.
+    Corrupted(String),
+    /// Listed in the index, but not there.
+    Missing(String),
+    /// There, but not listed in the index.
+    Extra(String),
+    /// Holds what belongs to the second one.
+    Reordered(String, String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Corrupted(what) => write!(f, "{} is corrupted", what),
+            Problem::Missing(what) => write!(f, "{} is missing", what),
+            Problem::Extra(what) => write!(f, "{} is extra", what),
+            Problem::Reordered(what, other) => write!(f, "{} holds {}", what, other),
+        }
+    }
+}
+
+fn describe(entry: &dir::Entry) -> String {
+    format!(
+        "entry {} ('{}')",
+        dir::entry_name(entry.number.unwrap_or(0)),
+        entry.path.display()
+    )
+}
+
+fn nonce_of(entry: &dir::Entry) -> Option<&[u8]> {
+    entry.nonce.as_ref().map(|n| &n[..])
+}
+
+/// Checks every entry listed in `entries`, reading the sealed data of each from its number with
+/// `read_entry`, which returns `None` if it is missing.
+fn check_entries<F>(
+    key: &[u8],
+    entries: &[dir::Entry],
+    mut read_entry: F,
+) -> io::Result<Vec<Problem>>
+where
+    F: FnMut(u32) -> io::Result<Option<Vec<u8>>>,
+{
+    let mut problems = Vec::new();
+
+    for e in entries {
+        let n = match e.number {
+            Some(n) => n,
+            None => continue,
+        };
+        let sealed = match read_entry(n)? {
+            Some(sealed) => sealed,
+            None => {
+                problems.push(Problem::Missing(describe(e)));
+                continue;
+            }
+        };
+
+        // The nonce ties the data to the entry it belongs to, if it is from this index at all.
+        let nonce = format::nonce(&sealed);
+
+        if nonce != nonce_of(e) {
+            match entries
+                .iter()
+                .find(|o| nonce.is_some() && nonce_of(o) == nonce)
+            {
+                Some(other) => problems.push(Problem::Reordered(describe(e), describe(other))),
+                None => problems.push(Problem::Corrupted(describe(e))),
+            }
+        } else if format::open(key, &sealed).is_err() {
+            problems.push(Problem::Corrupted(describe(e)));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Returns the names the sealed data of `entries` is stored under, along with the index.
+fn entry_names(entries: &[dir::Entry]) -> BTreeSet<u32> {
+    std::iter::once(0)
+        .chain(entries.iter().filter_map(|e| e.number))
+        .collect()
+}
+
+/// Verifies the directory `input`, given its already decrypted index `meta`.
+pub fn dir(key: &[u8], meta: &[u8], input: &Path) -> io::Result<Vec<Problem>> {
+    let entries = dir::parse_index(meta)?;
+
+    let mut problems = check_entries(key, &entries, |n| {
+        match fs::read(input.join(dir::entry_name(n))) {
+            Ok(sealed) => Ok(Some(sealed)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    })?;
+
+    problems.extend(extra_files(input, entry_names(&entries))?);
+    Ok(problems)
+}
+
+/// Reports the files in the directory `input` other than the sealed data numbered `expected`.
+pub fn extra_files(input: &Path, expected: BTreeSet<u32>) -> io::Result<Vec<Problem>> {
+    let expected = expected
+        .into_iter()
+        .map(dir::entry_name)
+        .collect::<BTreeSet<_>>();
+    let mut names = fs::read_dir(input)?
+        .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+
+    Ok(names
+        .into_iter()
+        .filter(|n| !expected.contains(n))
+        .map(|n| Problem::Extra(format!("'{}'", n)))
+        .collect())
+}
+
+/// Verifies an archive, given its already decrypted index `meta` and the sealed data from
+/// `archive::split`.
+pub fn archive(key: &[u8], meta: &[u8], sealed: &[&[u8]]) -> io::Result<Vec<Problem>> {
+    let entries = dir::parse_index(meta)?;
+
+    let mut problems = check_entries(key, &entries, |n| {
+        Ok(sealed.get(n as usize).map(|data| data.to_vec()))
+    })?;
+
+    let expected = entry_names(&entries);
+    for n in (0..sealed.len() as u32).filter(|n| !expected.contains(n)) {
+        problems.push(Problem::Extra(format!("entry {}", dir::entry_name(n))));
+    }
+
+    Ok(problems)
+}
+
+fn blob(n: usize) -> String {
+    format!("blob {}", dir::entry_name(n as u32))
+}
+
+/// Verifies the blobs of a packed directory, returning the archive they hold if there is no
+/// problem with them.
+pub fn blobs<B: AsRef<[u8]>>(key: &[u8], blobs: &[B]) -> (Vec<Problem>, Option<Vec<u8>>) {
+    let mut problems = Vec::new();
+    let mut data = Vec::new();
+    let mut last = None;
+
+    for (i, sealed) in blobs.iter().enumerate() {
+        let chunk = match format::open(key, sealed.as_ref()) {
+            Ok(chunk) if chunk.len() >= packed::CHUNK_HEADER_LEN => chunk,
+            _ => {
+                problems.push(Problem::Corrupted(blob(i)));
+                continue;
+            }
+        };
+
+        let n = u32::from_le_bytes(chunk[..4].try_into().unwrap()) as usize;
+        if n != i {
+            problems.push(Problem::Reordered(blob(i), blob(n)));
+        }
+        if chunk[4] == 1 {
+            last = Some(n);
+        }
+        data.extend_from_slice(&chunk[packed::CHUNK_HEADER_LEN..]);
+    }
+
+    match last {
+        Some(n) => (n + 1..blobs.len()).for_each(|i| problems.push(Problem::Extra(blob(i)))),
+        None => problems.push(Problem::Missing(blob(blobs.len()))),
+    }
+
+    match problems.is_empty() {
+        true => (problems, Some(data)),
+        false => (problems, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive;
+    use crate::test::{self, TempDir, KEY};
+
+    const OPTIONS: dir::Options = dir::Options {
+        follow_symlinks: false,
+        padding: None,
+    };
+
+    fn described(problems: io::Result<Vec<Problem>>) -> Vec<String> {
+        problems.unwrap().iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_dir() {
+        let temp = TempDir::new("verify-dir");
+        let enc = temp.join("enc");
+        dir::encrypt(&KEY, b"", &test::source_tree(&temp), &enc, &OPTIONS).unwrap();
+        let meta = format::open(&KEY, &fs::read(enc.join("00")).unwrap()).unwrap();
+        let path = |name: &str| enc.join(name);
+        assert!(described(dir(&KEY, &meta, &enc)).is_empty());
+
+        let (a, l) = (fs::read(path("01")).unwrap(), fs::read(path("02")).unwrap());
+        fs::write(path("01"), &l).unwrap();
+        fs::write(path("02"), &a).unwrap();
+        let mut b = fs::read(path("03")).unwrap();
+        b[format::HEADER_LEN] ^= 1;
+        fs::write(path("03"), &b).unwrap();
+        fs::write(path("zz"), b"").unwrap();
+
+        assert_eq!(
+            described(dir(&KEY, &meta, &enc)),
+            [
+                "entry 01 ('a') holds entry 02 ('l')",
+                "entry 02 ('l') holds entry 01 ('a')",
+                "entry 03 ('sub/b') is corrupted",
+                "'zz' is extra",
+            ]
+        );
+
+        fs::remove_file(path("03")).unwrap();
+        fs::remove_file(path("zz")).unwrap();
+        assert_eq!(
+            described(dir(&KEY, &meta, &enc))[2..],
+            ["entry 03 ('sub/b') is missing"]
+        );
+    }
+
+    #[test]
+    fn test_archive() {
+        let temp = TempDir::new("verify-archive");
+        let mut data = Vec::new();
+        archive::encrypt(&KEY, &test::source_tree(&temp), &mut data, &OPTIONS).unwrap();
+        let sealed = archive::split(&data).unwrap();
+        let meta = format::open(&KEY, sealed[0]).unwrap();
+        assert!(described(archive(&KEY, &meta, &sealed)).is_empty());
+
+        assert_eq!(
+            described(archive(&KEY, &meta, &sealed[..3])),
+            ["entry 03 ('sub/b') is missing"]
+        );
+        let extra = [&sealed[..], &sealed[3..]].concat();
+        assert_eq!(
+            described(archive(&KEY, &meta, &extra)),
+            ["entry 04 is extra"]
+        );
+    }
+
+    #[test]
+    fn test_blobs() {
+        let temp = TempDir::new("verify-blobs");
+        let src = test::source_tree(&temp);
+        let all = test::packed_blobs(&src);
+        let n = all.len();
+        let describe = |all: &[Vec<u8>]| {
+            let (problems, data) = blobs(&KEY, all);
+            assert_eq!(problems.is_empty(), data.is_some());
+            described(Ok(problems))
+        };
+
+        let (problems, data) = blobs(&KEY, &all);
+        assert!(problems.is_empty());
+        assert_eq!(data.unwrap(), packed::unpack(&KEY, &all).unwrap());
+
+        let mut swapped = all.clone();
+        swapped.swap(0, 1);
+        assert_eq!(
+            describe(&swapped),
+            ["blob 00 holds blob 01", "blob 01 holds blob 00"]
+        );
+
+        let mut tampered = all.clone();
+        tampered[1][format::HEADER_LEN] ^= 1;
+        assert_eq!(describe(&tampered), ["blob 01 is corrupted"]);
+
+        let missing = format!("blob {} is missing", dir::entry_name(n as u32 - 1));
+        assert_eq!(describe(&all[..n - 1]), [missing]);
+
+        // The last blob once more, after itself.
+        let (last, copy) = (dir::entry_name(n as u32 - 1), dir::entry_name(n as u32));
+        assert_eq!(
+            describe(&[&all[..], &all[n - 1..]].concat()),
+            [
+                format!("blob {} holds blob {}", copy, last),
+                format!("blob {} is extra", copy)
+            ]
+        );
+    }
+}